@@ -0,0 +1,9 @@
+#!/bin/sh
+# Regenerates src/github_schema.graphql from GitHub's published GraphQL
+# schema.  The schema file should only ever be changed by running this, so
+# that it matches what GitHub actually serves.
+set -e
+cd "$(dirname "$0")/.."
+curl -fsSL https://docs.github.com/public/fpt/schema.docs.graphql \
+  -o src/github_schema.graphql
+cargo build
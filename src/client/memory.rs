@@ -0,0 +1,559 @@
+//! A GitHub and a bug tracker that keep everything in memory, so that tasks
+//! can be run without a server.
+
+use super::{BugTracker, GitHubClient};
+use crate::query::{
+    CreatedIssue, FoundIssue, IssueComment, IssueKind, IssueLabel, KnownLabel, QueryError,
+    UpdatedIssue,
+};
+use failure::Error;
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, Default)]
+pub struct MemoryRepo {
+    pub id: String,
+    /// The contents of the repository's `config.toml`.
+    pub config: String,
+    pub labels: Vec<MemoryLabel>,
+    /// The repository's issues, pull requests and discussions.
+    pub issues: Vec<MemoryIssue>,
+}
+
+impl MemoryRepo {
+    pub fn issue(&self, number: i64) -> &MemoryIssue {
+        self.issues
+            .iter()
+            .find(|i| i.number == number)
+            .unwrap_or_else(|| panic!("no issue #{} in {}", number, self.id))
+    }
+
+    fn label_name(&self, id: &str) -> Option<&str> {
+        self.labels
+            .iter()
+            .find(|l| l.id == id)
+            .map(|l| l.name.as_str())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MemoryLabel {
+    pub id: String,
+    pub name: String,
+    pub color: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct MemoryIssue {
+    pub id: String,
+    pub kind: IssueKind,
+    pub number: i64,
+    pub title: String,
+    pub body: String,
+    pub updated_at: String,
+    pub closed: bool,
+    /// The IDs of the issue's labels.
+    pub labels: Vec<String>,
+    pub comments: Vec<IssueComment>,
+}
+
+/// A GitHub whose repositories are kept in memory, keyed by "owner/name".
+/// Everything that is created or changed is stamped with `now`.
+pub struct MemoryGitHub {
+    repos: HashMap<String, MemoryRepo>,
+    next_id: u64,
+    pub now: String,
+}
+
+impl MemoryGitHub {
+    pub fn new(now: &str) -> MemoryGitHub {
+        MemoryGitHub {
+            repos: HashMap::new(),
+            next_id: 1,
+            now: now.to_string(),
+        }
+    }
+
+    pub fn repo(&self, repo: &str) -> &MemoryRepo {
+        self.repos
+            .get(repo)
+            .unwrap_or_else(|| panic!("no repository {}", repo))
+    }
+
+    pub fn add_repo(&mut self, repo: &str, config: &str) {
+        let id = self.new_id("repo");
+        self.repos.insert(
+            repo.to_string(),
+            MemoryRepo {
+                id,
+                config: config.to_string(),
+                ..Default::default()
+            },
+        );
+    }
+
+    pub fn add_label(&mut self, repo: &str, name: &str, color: &str) -> String {
+        let id = self.new_id("label");
+        self.repo_mut(repo).labels.push(MemoryLabel {
+            id: id.clone(),
+            name: name.to_string(),
+            color: color.to_string(),
+        });
+        id
+    }
+
+    /// Adds an issue with the given label names, and returns its number.
+    pub fn add_issue(&mut self, repo: &str, title: &str, body: &str, labels: &[&str]) -> i64 {
+        self.add_item(repo, IssueKind::Issue, title, body, labels)
+    }
+
+    /// Adds an issue, pull request or discussion with the given label names,
+    /// and returns its number.
+    pub fn add_item(
+        &mut self,
+        repo: &str,
+        kind: IssueKind,
+        title: &str,
+        body: &str,
+        labels: &[&str],
+    ) -> i64 {
+        let id = self.new_id("issue");
+        let now = self.now.clone();
+        let repo = self.repo_mut(repo);
+        let labels = labels
+            .iter()
+            .map(|name| {
+                repo.labels
+                    .iter()
+                    .find(|l| l.name == *name)
+                    .unwrap_or_else(|| panic!("no label {}", name))
+                    .id
+                    .clone()
+            })
+            .collect();
+        let number = repo.issues.len() as i64 + 1;
+        repo.issues.push(MemoryIssue {
+            id,
+            kind,
+            number,
+            title: title.to_string(),
+            body: body.to_string(),
+            updated_at: now,
+            closed: false,
+            labels,
+            comments: Vec::new(),
+        });
+        number
+    }
+
+    /// Adds a comment to an issue, pull request or discussion, and returns
+    /// its URL.
+    pub fn add_comment(&mut self, repo: &str, number: i64, body: &str) -> String {
+        let id = self.next_id;
+        self.next_id += 1;
+        let now = self.now.clone();
+        let issue = self.issue_mut(repo, number);
+        let anchor = match issue.kind {
+            IssueKind::Discussion => "discussioncomment",
+            _ => "issuecomment",
+        };
+        let url = format!(
+            "{}#{}-{}",
+            issue
+                .kind
+                .url(&format!("https://github.com/{}", repo), number),
+            anchor,
+            id
+        );
+        issue.comments.push(IssueComment {
+            url: url.clone(),
+            created_at: now.clone(),
+            last_edited_at: None,
+            body_text: body.to_string(),
+        });
+        issue.updated_at = now;
+        url
+    }
+
+    pub fn edit_comment(&mut self, repo: &str, number: i64, url: &str, body: &str) {
+        let now = self.now.clone();
+        let issue = self.issue_mut(repo, number);
+        let comment = issue
+            .comments
+            .iter_mut()
+            .find(|c| c.url == url)
+            .unwrap_or_else(|| panic!("no comment {}", url));
+        comment.body_text = body.to_string();
+        comment.last_edited_at = Some(now.clone());
+        issue.updated_at = now;
+    }
+
+    pub fn close(&mut self, repo: &str, number: i64) {
+        let now = self.now.clone();
+        let issue = self.issue_mut(repo, number);
+        issue.closed = true;
+        issue.updated_at = now;
+    }
+
+    fn new_id(&mut self, kind: &str) -> String {
+        let id = format!("{}-{}", kind, self.next_id);
+        self.next_id += 1;
+        id
+    }
+
+    fn repo_mut(&mut self, repo: &str) -> &mut MemoryRepo {
+        self.repos
+            .get_mut(repo)
+            .unwrap_or_else(|| panic!("no repository {}", repo))
+    }
+
+    fn issue_mut(&mut self, repo: &str, number: i64) -> &mut MemoryIssue {
+        self.repo_mut(repo)
+            .issues
+            .iter_mut()
+            .find(|i| i.number == number)
+            .unwrap_or_else(|| panic!("no issue #{} in {}", number, repo))
+    }
+
+    fn find_repo(&self, repo_owner: &str, repo_name: &str) -> Result<&MemoryRepo, Error> {
+        let repo = format!("{}/{}", repo_owner, repo_name);
+        self.repos
+            .get(&repo)
+            .ok_or_else(|| QueryError::NotFound(format!("repository {}", repo)).into())
+    }
+
+    /// Finds an issue, pull request or discussion by its number, which must
+    /// be of the given kind.
+    fn find_issue(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        kind: IssueKind,
+        number: i64,
+    ) -> Result<(&MemoryRepo, &MemoryIssue), Error> {
+        let repo = self.find_repo(repo_owner, repo_name)?;
+        let issue = repo
+            .issues
+            .iter()
+            .find(|i| i.kind == kind && i.number == number)
+            .ok_or_else(|| QueryError::NotFound(format!("{:?} {}", kind, number)))?;
+        Ok((repo, issue))
+    }
+
+    /// Returns the items of the given kind updated since the given time,
+    /// oldest first.
+    fn updated_items(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        kind: IssueKind,
+        since: &str,
+    ) -> Result<Vec<UpdatedIssue>, Error> {
+        let repo = self.find_repo(repo_owner, repo_name)?;
+        let mut issues = repo
+            .issues
+            .iter()
+            .filter(|i| i.kind == kind && i.updated_at.as_str() >= since)
+            .map(|i| updated_issue(repo, i))
+            .collect::<Vec<_>>();
+        issues.sort_by(|a, b| a.updated_at.cmp(&b.updated_at));
+        Ok(issues)
+    }
+
+    fn find_repo_by_id(&mut self, id: &str) -> Result<&mut MemoryRepo, Error> {
+        self.repos
+            .values_mut()
+            .find(|r| r.id == id)
+            .ok_or_else(|| QueryError::NotFound(format!("repository {}", id)).into())
+    }
+
+    /// Finds an issue by its ID, and stamps it as updated.
+    fn update_issue_by_id(&mut self, id: &str) -> Result<&mut MemoryIssue, Error> {
+        let now = self.now.clone();
+        let issue = self
+            .repos
+            .values_mut()
+            .flat_map(|r| r.issues.iter_mut())
+            .find(|i| i.id == id)
+            .ok_or_else(|| QueryError::NotFound(format!("issue {}", id)))?;
+        issue.updated_at = now;
+        Ok(issue)
+    }
+}
+
+fn updated_issue(repo: &MemoryRepo, issue: &MemoryIssue) -> UpdatedIssue {
+    UpdatedIssue {
+        id: issue.id.clone(),
+        issue_number: issue.number,
+        issue_title: issue.title.clone(),
+        updated_at: issue.updated_at.clone(),
+        closed: issue.closed,
+        issue_labels: repo
+            .labels
+            .iter()
+            .filter(|l| issue.labels.contains(&l.id))
+            .map(|l| IssueLabel {
+                name: l.name.clone(),
+                color: l.color.clone(),
+            })
+            .collect(),
+    }
+}
+
+impl GitHubClient for MemoryGitHub {
+    fn repo_config(&self, repo_owner: &str, repo_name: &str) -> Result<String, Error> {
+        Ok(self.find_repo(repo_owner, repo_name)?.config.clone())
+    }
+
+    fn updated_issues(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        since: &str,
+    ) -> Result<Vec<UpdatedIssue>, Error> {
+        self.updated_items(repo_owner, repo_name, IssueKind::Issue, since)
+    }
+
+    fn issue(&self, repo_owner: &str, repo_name: &str, number: i64) -> Result<UpdatedIssue, Error> {
+        let (repo, issue) = self.find_issue(repo_owner, repo_name, IssueKind::Issue, number)?;
+        Ok(updated_issue(repo, issue))
+    }
+
+    fn pull_request(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        number: i64,
+    ) -> Result<UpdatedIssue, Error> {
+        let (repo, issue) =
+            self.find_issue(repo_owner, repo_name, IssueKind::PullRequest, number)?;
+        Ok(updated_issue(repo, issue))
+    }
+
+    fn discussion(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        number: i64,
+    ) -> Result<UpdatedIssue, Error> {
+        let (repo, issue) =
+            self.find_issue(repo_owner, repo_name, IssueKind::Discussion, number)?;
+        Ok(updated_issue(repo, issue))
+    }
+
+    fn issue_comments(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        number: i64,
+    ) -> Result<Vec<IssueComment>, Error> {
+        Ok(self
+            .find_issue(repo_owner, repo_name, IssueKind::Issue, number)?
+            .1
+            .comments
+            .clone())
+    }
+
+    fn updated_pull_requests(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        since: &str,
+    ) -> Result<Vec<UpdatedIssue>, Error> {
+        self.updated_items(repo_owner, repo_name, IssueKind::PullRequest, since)
+    }
+
+    fn pull_request_comments(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        number: i64,
+    ) -> Result<Vec<IssueComment>, Error> {
+        Ok(self
+            .find_issue(repo_owner, repo_name, IssueKind::PullRequest, number)?
+            .1
+            .comments
+            .clone())
+    }
+
+    fn updated_discussions(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        since: &str,
+    ) -> Result<Vec<UpdatedIssue>, Error> {
+        self.updated_items(repo_owner, repo_name, IssueKind::Discussion, since)
+    }
+
+    fn discussion_comments(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        number: i64,
+    ) -> Result<Vec<IssueComment>, Error> {
+        Ok(self
+            .find_issue(repo_owner, repo_name, IssueKind::Discussion, number)?
+            .1
+            .comments
+            .clone())
+    }
+
+    fn issue_title_and_body(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        number: i64,
+    ) -> Result<(String, String), Error> {
+        let issue = self
+            .find_issue(repo_owner, repo_name, IssueKind::Issue, number)?
+            .1;
+        Ok((issue.title.clone(), issue.body.clone()))
+    }
+
+    fn known_labels(&self, repo_owner: &str, repo_name: &str) -> Result<Vec<KnownLabel>, Error> {
+        Ok(self
+            .find_repo(repo_owner, repo_name)?
+            .labels
+            .iter()
+            .map(|l| KnownLabel {
+                id: l.id.clone(),
+                name: l.name.clone(),
+            })
+            .collect())
+    }
+
+    fn repo_id(&self, repo_owner: &str, repo_name: &str) -> Result<Option<String>, Error> {
+        Ok(self
+            .find_repo(repo_owner, repo_name)
+            .ok()
+            .map(|r| r.id.clone()))
+    }
+
+    fn search_issues(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        text: &str,
+    ) -> Result<Vec<FoundIssue>, Error> {
+        Ok(self
+            .find_repo(repo_owner, repo_name)?
+            .issues
+            .iter()
+            .filter(|i| i.kind == IssueKind::Issue && i.body.contains(text))
+            .map(|i| FoundIssue {
+                id: i.id.clone(),
+                number: i.number,
+                body: i.body.clone(),
+            })
+            .collect())
+    }
+
+    fn create_label(&mut self, repo_id: &str, name: &str, color: &str) -> Result<String, Error> {
+        let id = self.new_id("label");
+        self.find_repo_by_id(repo_id)?.labels.push(MemoryLabel {
+            id: id.clone(),
+            name: name.to_string(),
+            color: color.to_string(),
+        });
+        Ok(id)
+    }
+
+    fn create_issue(
+        &mut self,
+        repo_id: &str,
+        title: String,
+        body: Option<String>,
+        labels: Option<Vec<String>>,
+    ) -> Result<CreatedIssue, Error> {
+        let id = self.new_id("issue");
+        let now = self.now.clone();
+        let repo = self.find_repo_by_id(repo_id)?;
+        let labels = labels.unwrap_or_default();
+        if let Some(label) = labels.iter().find(|l| repo.label_name(l).is_none()) {
+            return Err(QueryError::NotFound(format!("label {}", label)).into());
+        }
+        let number = repo.issues.len() as i64 + 1;
+        repo.issues.push(MemoryIssue {
+            id: id.clone(),
+            kind: IssueKind::Issue,
+            number,
+            title,
+            body: body.unwrap_or_default(),
+            updated_at: now,
+            closed: false,
+            labels,
+            comments: Vec::new(),
+        });
+        Ok(CreatedIssue { id, number })
+    }
+
+    fn remove_labels(&mut self, labelable: String, labels: Vec<String>) -> Result<(), Error> {
+        self.update_issue_by_id(&labelable)?
+            .labels
+            .retain(|l| !labels.contains(l));
+        Ok(())
+    }
+
+    fn close_issue(&mut self, id: String) -> Result<(), Error> {
+        self.update_issue_by_id(&id)?.closed = true;
+        Ok(())
+    }
+
+    fn add_issue_comment(&mut self, issue_id: String, body: String) -> Result<(), Error> {
+        let (repo, number) = self
+            .repos
+            .iter()
+            .flat_map(|(repo, r)| r.issues.iter().map(move |i| (repo, i)))
+            .find(|(_, i)| i.id == issue_id)
+            .map(|(repo, i)| (repo.clone(), i.number))
+            .ok_or_else(|| QueryError::NotFound(format!("issue {}", issue_id)))?;
+        self.add_comment(&repo, number, &body);
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MemoryBug {
+    pub product: String,
+    pub component: String,
+    pub summary: String,
+    pub description: String,
+    pub urls: Vec<String>,
+}
+
+/// A bug tracker that keeps the bugs filed in memory.  A bug's ID is its
+/// index in `bugs` plus one.
+#[derive(Default)]
+pub struct MemoryBugTracker {
+    pub bugs: Vec<MemoryBug>,
+    /// Whether filing bugs fails, as if the bug tracker were down.
+    pub unavailable: bool,
+}
+
+impl MemoryBugTracker {
+    pub fn bug_url(id: usize) -> String {
+        format!("https://bugzilla.test/show_bug.cgi?id={}", id)
+    }
+}
+
+impl BugTracker for MemoryBugTracker {
+    fn file_bug(
+        &mut self,
+        product: String,
+        component: String,
+        summary: String,
+        description: String,
+        urls: Vec<String>,
+    ) -> Result<String, Error> {
+        if self.unavailable {
+            return Err(QueryError::HttpStatus(503).into());
+        }
+        self.bugs.push(MemoryBug {
+            product,
+            component,
+            summary,
+            description,
+            urls,
+        });
+        Ok(MemoryBugTracker::bug_url(self.bugs.len()))
+    }
+}
@@ -0,0 +1,135 @@
+//! The operations that tasks perform on GitHub and Bugzilla.
+//!
+//! Tasks are given a `GitHubClient` and a `BugTracker` to run against, rather
+//! than calling the `query` functions themselves.  The ones used for real
+//! runs are `query::GitHub` and `query::Bugzilla`, which hold the endpoints
+//! and credentials from the config file and print mutations instead of
+//! performing them in dry-run mode.
+
+#[cfg(test)]
+mod memory;
+
+use crate::query::{CreatedIssue, FoundIssue, IssueComment, KnownLabel, UpdatedIssue};
+use failure::Error;
+
+#[cfg(test)]
+pub use memory::{MemoryBugTracker, MemoryGitHub, MemoryIssue};
+
+pub trait GitHubClient {
+    /// Returns the contents of a repository's `config.toml`.
+    fn repo_config(&self, repo_owner: &str, repo_name: &str) -> Result<String, Error>;
+
+    /// Returns the issues updated since the given time, oldest first.
+    fn updated_issues(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        since: &str,
+    ) -> Result<Vec<UpdatedIssue>, Error>;
+
+    fn issue(&self, repo_owner: &str, repo_name: &str, number: i64) -> Result<UpdatedIssue, Error>;
+
+    fn pull_request(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        number: i64,
+    ) -> Result<UpdatedIssue, Error>;
+
+    fn discussion(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        number: i64,
+    ) -> Result<UpdatedIssue, Error>;
+
+    /// Returns all the comments on an issue, oldest first.
+    fn issue_comments(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        number: i64,
+    ) -> Result<Vec<IssueComment>, Error>;
+
+    /// Returns the pull requests updated since the given time, oldest first.
+    fn updated_pull_requests(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        since: &str,
+    ) -> Result<Vec<UpdatedIssue>, Error>;
+
+    /// Returns the comments on a pull request, as for `issue_comments`.
+    fn pull_request_comments(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        number: i64,
+    ) -> Result<Vec<IssueComment>, Error>;
+
+    /// Returns the discussions updated since the given time, oldest first.
+    fn updated_discussions(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        since: &str,
+    ) -> Result<Vec<UpdatedIssue>, Error>;
+
+    /// Returns the comments on a discussion, as for `issue_comments`.
+    fn discussion_comments(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        number: i64,
+    ) -> Result<Vec<IssueComment>, Error>;
+
+    fn issue_title_and_body(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        number: i64,
+    ) -> Result<(String, String), Error>;
+
+    fn known_labels(&self, repo_owner: &str, repo_name: &str) -> Result<Vec<KnownLabel>, Error>;
+
+    fn repo_id(&self, repo_owner: &str, repo_name: &str) -> Result<Option<String>, Error>;
+
+    /// Searches for issues in a repository whose body matches the given
+    /// text.  The search may be fuzzy, so callers should check the returned
+    /// issue bodies themselves.
+    fn search_issues(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        text: &str,
+    ) -> Result<Vec<FoundIssue>, Error>;
+
+    /// Creates a label, and returns its ID.
+    fn create_label(&mut self, repo_id: &str, name: &str, color: &str) -> Result<String, Error>;
+
+    fn create_issue(
+        &mut self,
+        repo_id: &str,
+        title: String,
+        body: Option<String>,
+        labels: Option<Vec<String>>,
+    ) -> Result<CreatedIssue, Error>;
+
+    fn remove_labels(&mut self, labelable: String, labels: Vec<String>) -> Result<(), Error>;
+
+    fn close_issue(&mut self, id: String) -> Result<(), Error>;
+
+    fn add_issue_comment(&mut self, issue_id: String, body: String) -> Result<(), Error>;
+}
+
+pub trait BugTracker {
+    /// Files a bug, and returns its URL.
+    fn file_bug(
+        &mut self,
+        product: String,
+        component: String,
+        summary: String,
+        description: String,
+        urls: Vec<String>,
+    ) -> Result<String, Error>;
+}
@@ -1,6 +1,11 @@
+use crate::logging::LogFormat;
+use crate::resolutions::{ResolutionExtractor, ResolutionsConfig};
+use crate::state::StateBackend;
 use failure::{format_err, Error, ResultExt};
 use lazy_static::lazy_static;
 use regex::Regex;
+use reqwest::Url;
+use std::collections::HashSet;
 use std::fs::File;
 use std::io::Read;
 
@@ -8,12 +13,177 @@ use std::io::Read;
 pub struct Config {
     pub github_key: String,
     pub bugzilla_key: String,
-    pub wg_repo_owner: String,
-    pub wg_repo_name: String,
+    /// A single WG repository to track.  Equivalent to a `[[wg_repos]]`
+    /// entry, and kept for compatibility with older config files.
+    wg_repo_owner: Option<String>,
+    wg_repo_name: Option<String>,
+    #[serde(default)]
+    pub wg_repos: Vec<WgRepo>,
+    /// The decisions repository that resolutions not matched by any of the
+    /// `routes` are filed in.
     pub decisions_repo_owner: String,
     pub decisions_repo_name: String,
+    /// Rules for filing resolutions in other decisions repositories, tried
+    /// in order.
+    #[serde(default)]
+    pub routes: Vec<Route>,
     pub state_directory: String,
     pub start_date: String,
+    #[serde(default = "default_github_endpoint")]
+    pub github_endpoint: String,
+    #[serde(default = "default_bugzilla_url")]
+    pub bugzilla_url: String,
+    #[serde(default = "default_raw_content_url")]
+    pub raw_content_url: String,
+    #[serde(default = "default_max_task_attempts")]
+    pub max_task_attempts: u32,
+    #[serde(default = "default_retry_delay_seconds")]
+    pub retry_delay_seconds: i64,
+    #[serde(default = "default_rate_limit_minimum")]
+    pub rate_limit_minimum: i64,
+    #[serde(default = "default_rate_limit_max_sleep_seconds")]
+    pub rate_limit_max_sleep_seconds: i64,
+    #[serde(default)]
+    pub page_sizes: PageSizes,
+    /// The secret that GitHub webhook deliveries are signed with.  Required
+    /// for `serve` mode.
+    pub webhook_secret: Option<String>,
+    #[serde(default = "default_webhook_address")]
+    pub webhook_address: String,
+    /// How often `daemon` mode polls for updates, and how often `serve` mode
+    /// does, to catch anything that webhook deliveries were missed for.
+    #[serde(default = "default_poll_interval_seconds")]
+    pub poll_interval_seconds: u64,
+    /// The default resolution patterns for WG repositories that don't
+    /// specify their own.
+    #[serde(default)]
+    resolutions: ResolutionsConfig,
+    #[serde(default)]
+    pub log_format: LogFormat,
+    /// The address to serve metrics on in `daemon` and `serve` modes.
+    pub metrics_address: Option<String>,
+    /// How the state in `state_directory` is stored.
+    #[serde(default)]
+    pub state_backend: StateBackend,
+    /// The number of snapshots of the state to keep, taken before each run.
+    /// Zero disables snapshots.
+    #[serde(default = "default_state_snapshots")]
+    pub state_snapshots: usize,
+    /// How often `daemon` and `serve` mode take a snapshot, rather than
+    /// taking one every poll.
+    #[serde(default = "default_state_snapshot_interval_seconds")]
+    pub state_snapshot_interval_seconds: u64,
+    /// How long to remember which comments and decisions issues have been
+    /// handled, once our cursors have moved past them.
+    #[serde(default = "default_handled_retention_days")]
+    pub handled_retention_days: i64,
+    #[serde(skip)]
+    pub dry_run: bool,
+}
+
+/// The `[page_sizes]` table of the config file: how many items to ask GitHub
+/// for in each page of query results, which can be at most 100.
+#[derive(Clone, Copy, Deserialize)]
+pub struct PageSizes {
+    #[serde(default = "default_page_size")]
+    pub issues: i64,
+    #[serde(default = "default_page_size")]
+    pub comments: i64,
+    #[serde(default = "default_page_size")]
+    pub labels: i64,
+}
+
+impl Default for PageSizes {
+    fn default() -> PageSizes {
+        PageSizes {
+            issues: default_page_size(),
+            comments: default_page_size(),
+            labels: default_page_size(),
+        }
+    }
+}
+
+/// A WG repository whose issues we track resolutions in.
+#[derive(Deserialize)]
+pub struct WgRepo {
+    pub owner: String,
+    pub name: String,
+    resolutions: Option<ResolutionsConfig>,
+    #[serde(skip)]
+    pub resolution_extractor: ResolutionExtractor,
+}
+
+impl WgRepo {
+    /// Returns the "owner/name" string that identifies the repository.
+    pub fn id(&self) -> String {
+        format!("{}/{}", self.owner, self.name)
+    }
+
+    pub fn url(&self) -> String {
+        format!("https://github.com/{}/{}", self.owner, self.name)
+    }
+}
+
+/// A rule that sends resolutions from matching WG issues to a particular
+/// decisions repository.  An issue matches if it satisfies all of the given
+/// conditions.
+#[derive(Deserialize)]
+pub struct Route {
+    /// The "owner/name" of the decisions repository.
+    pub decisions_repo: String,
+    /// The "owner/name" of the WG repository the issue must be in.
+    wg_repo: Option<String>,
+    /// Patterns for WG issue labels, where `*` matches any sequence of
+    /// characters.  The issue must have a label matching one of them.
+    labels: Option<Vec<String>>,
+    /// A regular expression the WG issue title must match.
+    title: Option<String>,
+    #[serde(skip)]
+    label_res: Vec<Regex>,
+    #[serde(skip)]
+    title_re: Option<Regex>,
+}
+
+impl Route {
+    fn compile(&mut self) -> Result<(), Error> {
+        validate_repo_id("route decisions_repo", &self.decisions_repo)?;
+        if let Some(wg_repo) = &self.wg_repo {
+            validate_repo_id("route wg_repo", wg_repo)?;
+        }
+        if let Some(labels) = &self.labels {
+            self.label_res = labels
+                .iter()
+                .map(|l| {
+                    let pattern = format!("^{}$", regex::escape(l).replace(r"\*", ".*"));
+                    Regex::new(&pattern).unwrap()
+                })
+                .collect();
+        }
+        if let Some(title) = &self.title {
+            self.title_re = Some(
+                Regex::new(title)
+                    .with_context(|_| format!("invalid route title pattern '{}'", title))?,
+            );
+        }
+        Ok(())
+    }
+
+    fn matches(&self, wg_repo: &str, labels: &[&str], title: &str) -> bool {
+        if matches!(&self.wg_repo, Some(r) if r != wg_repo) {
+            return false;
+        }
+        if self.labels.is_some()
+            && !labels
+                .iter()
+                .any(|l| self.label_res.iter().any(|re| re.is_match(l)))
+        {
+            return false;
+        }
+        match &self.title_re {
+            Some(re) => re.is_match(title),
+            None => true,
+        }
+    }
 }
 
 impl Config {
@@ -23,11 +193,48 @@ impl Config {
             .context("could not open config file")?
             .read_to_string(&mut toml)
             .context("could not read config file")?;
+        Config::from_toml(&toml)
+    }
+
+    pub fn from_toml(toml: &str) -> Result<Config, Error> {
+        let mut config: Config = toml::from_str(toml).context("could not parse config file")?;
 
-        let config: Config = toml::from_str(&toml).context("could not parse config file")?;
+        match (config.wg_repo_owner.take(), config.wg_repo_name.take()) {
+            (Some(owner), Some(name)) => config.wg_repos.insert(
+                0,
+                WgRepo {
+                    owner,
+                    name,
+                    resolutions: None,
+                    resolution_extractor: Default::default(),
+                },
+            ),
+            (None, None) => {}
+            _ => {
+                return Err(format_err!(
+                    "config file must have both wg_repo_owner and wg_repo_name, or neither"
+                ))
+            }
+        }
 
-        validate_syntax("wg_repo_owner", &config.wg_repo_owner, &REPO_ID_RE)?;
-        validate_syntax("wg_repo_name", &config.wg_repo_name, &REPO_ID_RE)?;
+        if config.wg_repos.is_empty() {
+            return Err(format_err!("config file has no WG repositories"));
+        }
+
+        let mut wg_repo_ids = HashSet::new();
+        for wg_repo in &mut config.wg_repos {
+            validate_syntax("WG repository owner", &wg_repo.owner, &REPO_ID_RE)?;
+            validate_syntax("WG repository name", &wg_repo.name, &REPO_ID_RE)?;
+            if !wg_repo_ids.insert(wg_repo.id()) {
+                return Err(format_err!(
+                    "config file lists WG repository {} more than once",
+                    wg_repo.id()
+                ));
+            }
+            wg_repo.resolution_extractor = ResolutionExtractor::new(
+                wg_repo.resolutions.as_ref().unwrap_or(&config.resolutions),
+            )?;
+        }
         validate_syntax(
             "decisions_repo_owner",
             &config.decisions_repo_owner,
@@ -38,26 +245,157 @@ impl Config {
             &config.decisions_repo_name,
             &REPO_ID_RE,
         )?;
+        for route in &mut config.routes {
+            route.compile()?;
+        }
         validate_syntax("start_date", &config.start_date, &DATE_RE)?;
+        if !matches!(Url::parse(&config.github_endpoint), Ok(url) if url.has_host()) {
+            return Err(format_err!(
+                "config file github_endpoint value is not a valid URL"
+            ));
+        }
+        for (key, page_size) in &[
+            ("issues", config.page_sizes.issues),
+            ("comments", config.page_sizes.comments),
+            ("labels", config.page_sizes.labels),
+        ] {
+            if !(1..=100).contains(page_size) {
+                return Err(format_err!(
+                    "config file page_sizes {} value must be between 1 and 100",
+                    key
+                ));
+            }
+        }
 
         Ok(config)
     }
 
-    pub fn wg_repo_url(&self) -> String {
-        format!(
-            "https://github.com/{}/{}",
-            self.wg_repo_owner, self.wg_repo_name
-        )
+    /// Looks up a WG repository by its "owner/name" ID.
+    pub fn wg_repo(&self, id: &str) -> Result<&WgRepo, Error> {
+        self.wg_repos
+            .iter()
+            .find(|r| r.id() == id)
+            .ok_or_else(|| format_err!("WG repository {} is not in the config file", id))
     }
 
-    pub fn decisions_repo_url(&self) -> String {
+    /// Returns the "owner/name" of the default decisions repository.
+    pub fn default_decisions_repo(&self) -> String {
+        format!("{}/{}", self.decisions_repo_owner, self.decisions_repo_name)
+    }
+
+    /// Returns the "owner/name" of every decisions repository, starting with
+    /// the default one.
+    pub fn decisions_repos(&self) -> Vec<String> {
+        let mut repos = vec![self.default_decisions_repo()];
+        for route in &self.routes {
+            if !repos.contains(&route.decisions_repo) {
+                repos.push(route.decisions_repo.clone());
+            }
+        }
+        repos
+    }
+
+    /// Returns the "owner/name" of the decisions repository that resolutions
+    /// for a WG issue should be filed in.
+    pub fn route(&self, wg_repo: &str, labels: &[&str], title: &str) -> String {
+        self.routes
+            .iter()
+            .find(|r| r.matches(wg_repo, labels, title))
+            .map_or_else(
+                || self.default_decisions_repo(),
+                |r| r.decisions_repo.clone(),
+            )
+    }
+
+    /// Returns the web URL of a decisions repository, on the GitHub instance
+    /// that `github_endpoint` belongs to.
+    pub fn decisions_repo_url(&self, decisions_repo: &str) -> String {
         format!(
-            "https://github.com/{}/{}",
-            self.decisions_repo_owner, self.decisions_repo_name
+            "{}/{}",
+            github_web_url(&self.github_endpoint),
+            decisions_repo
         )
     }
 }
 
+fn default_github_endpoint() -> String {
+    String::from("https://api.github.com/graphql")
+}
+
+fn default_bugzilla_url() -> String {
+    String::from("https://bugzilla.mozilla.org")
+}
+
+fn default_raw_content_url() -> String {
+    String::from("https://raw.githubusercontent.com")
+}
+
+fn default_max_task_attempts() -> u32 {
+    5
+}
+
+fn default_retry_delay_seconds() -> i64 {
+    300
+}
+
+fn default_rate_limit_minimum() -> i64 {
+    100
+}
+
+fn default_rate_limit_max_sleep_seconds() -> i64 {
+    3600
+}
+
+fn default_page_size() -> i64 {
+    100
+}
+
+fn default_webhook_address() -> String {
+    String::from("127.0.0.1:8000")
+}
+
+fn default_state_snapshots() -> usize {
+    10
+}
+
+fn default_state_snapshot_interval_seconds() -> u64 {
+    24 * 60 * 60
+}
+
+fn default_handled_retention_days() -> i64 {
+    365
+}
+
+fn default_poll_interval_seconds() -> u64 {
+    900
+}
+
+/// Returns the base URL of the GitHub web interface for a GraphQL endpoint.
+/// github.com serves its API from the api. subdomain, while GitHub
+/// Enterprise serves it from an /api path on the same host.
+fn github_web_url(endpoint: &str) -> String {
+    let url = Url::parse(endpoint).unwrap();
+    let host = url.host_str().unwrap_or_default();
+    let host = host.strip_prefix("api.").unwrap_or(host);
+    match url.port() {
+        Some(port) => format!("{}://{}:{}", url.scheme(), host, port),
+        None => format!("{}://{}", url.scheme(), host),
+    }
+}
+
+/// Validates an "owner/name" repository ID.
+fn validate_repo_id(key: &str, value: &str) -> Result<(), Error> {
+    let (owner, name) = split_repo_id(value);
+    validate_syntax(key, owner, &REPO_ID_RE)?;
+    validate_syntax(key, name, &REPO_ID_RE)
+}
+
+/// Splits an "owner/name" repository ID into its owner and name.
+pub fn split_repo_id(id: &str) -> (&str, &str) {
+    let mut bits = id.splitn(2, '/');
+    (bits.next().unwrap(), bits.next().unwrap_or_default())
+}
+
 fn validate_syntax(key: &str, value: &str, regex: &Regex) -> Result<(), Error> {
     if !regex.is_match(value) {
         return Err(format_err!(
@@ -72,3 +410,242 @@ lazy_static! {
     static ref DATE_RE: Regex = Regex::new(r"^(\d\d\d\d)-(\d\d)-(\d\d)$").unwrap();
     static ref REPO_ID_RE: Regex = Regex::new(r"^[0-9A-Za-z_-]+$").unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+        github_key = "github-key"
+        bugzilla_key = "bugzilla-key"
+        decisions_repo_owner = "mozilla"
+        decisions_repo_name = "wg-decisions"
+        state_directory = "state"
+        start_date = "2019-01-01"
+
+        [[wg_repos]]
+        owner = "w3c"
+        name = "csswg-drafts"
+
+        [[wg_repos]]
+        owner = "w3c"
+        name = "fxtf-drafts"
+    "#;
+
+    fn with_routes(routes: &str) -> Result<Config, Error> {
+        Config::from_toml(&format!("{}\n{}", CONFIG, routes))
+    }
+
+    #[test]
+    fn unrouted_issues_use_default_decisions_repo() {
+        let config = with_routes("").unwrap();
+        assert_eq!(
+            config.route("w3c/csswg-drafts", &["css-grid-2"], "Title"),
+            "mozilla/wg-decisions"
+        );
+        assert_eq!(config.decisions_repos(), vec!["mozilla/wg-decisions"]);
+    }
+
+    #[test]
+    fn routes_match_wg_repo() {
+        let config = with_routes(
+            r#"
+            [[routes]]
+            decisions_repo = "mozilla/fxtf-decisions"
+            wg_repo = "w3c/fxtf-drafts"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.route("w3c/fxtf-drafts", &[], "Title"),
+            "mozilla/fxtf-decisions"
+        );
+        assert_eq!(
+            config.route("w3c/csswg-drafts", &[], "Title"),
+            "mozilla/wg-decisions"
+        );
+    }
+
+    #[test]
+    fn routes_match_label_globs() {
+        let config = with_routes(
+            r#"
+            [[routes]]
+            decisions_repo = "mozilla/grid-decisions"
+            labels = ["css-grid-*", "css-subgrid"]
+            "#,
+        )
+        .unwrap();
+        let route = |labels: &[&str]| config.route("w3c/csswg-drafts", labels, "Title");
+        assert_eq!(route(&["css-grid-2"]), "mozilla/grid-decisions");
+        assert_eq!(route(&["a11y", "css-grid-"]), "mozilla/grid-decisions");
+        assert_eq!(route(&["css-subgrid"]), "mozilla/grid-decisions");
+        assert_eq!(route(&["css-subgrid-1"]), "mozilla/wg-decisions");
+        assert_eq!(route(&["xcss-grid-2"]), "mozilla/wg-decisions");
+        assert_eq!(route(&[]), "mozilla/wg-decisions");
+    }
+
+    #[test]
+    fn label_globs_escape_other_characters() {
+        let config = with_routes(
+            r#"
+            [[routes]]
+            decisions_repo = "mozilla/grid-decisions"
+            labels = ["css.grid+"]
+            "#,
+        )
+        .unwrap();
+        let route = |label: &str| config.route("w3c/csswg-drafts", &[label], "Title");
+        assert_eq!(route("css.grid+"), "mozilla/grid-decisions");
+        assert_eq!(route("cssxgrid"), "mozilla/wg-decisions");
+    }
+
+    #[test]
+    fn routes_match_title_regex() {
+        let config = with_routes(
+            r#"
+            [[routes]]
+            decisions_repo = "mozilla/grid-decisions"
+            title = "^\\[css-grid"
+            "#,
+        )
+        .unwrap();
+        let route = |title: &str| config.route("w3c/csswg-drafts", &[], title);
+        assert_eq!(route("[css-grid-2] Subgrid gaps"), "mozilla/grid-decisions");
+        assert_eq!(
+            route("Re: [css-grid-2] Subgrid gaps"),
+            "mozilla/wg-decisions"
+        );
+    }
+
+    #[test]
+    fn routes_require_every_condition() {
+        let config = with_routes(
+            r#"
+            [[routes]]
+            decisions_repo = "mozilla/grid-decisions"
+            wg_repo = "w3c/csswg-drafts"
+            labels = ["css-grid-*"]
+            title = "gap"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.route("w3c/csswg-drafts", &["css-grid-2"], "Subgrid gaps"),
+            "mozilla/grid-decisions"
+        );
+        assert_eq!(
+            config.route("w3c/fxtf-drafts", &["css-grid-2"], "Subgrid gaps"),
+            "mozilla/wg-decisions"
+        );
+        assert_eq!(
+            config.route("w3c/csswg-drafts", &["css-flexbox-1"], "Subgrid gaps"),
+            "mozilla/wg-decisions"
+        );
+        assert_eq!(
+            config.route("w3c/csswg-drafts", &["css-grid-2"], "Subgrid tracks"),
+            "mozilla/wg-decisions"
+        );
+    }
+
+    #[test]
+    fn first_matching_route_wins() {
+        let config = with_routes(
+            r#"
+            [[routes]]
+            decisions_repo = "mozilla/grid-decisions"
+            labels = ["css-grid-*"]
+
+            [[routes]]
+            decisions_repo = "mozilla/css-decisions"
+            labels = ["css-*"]
+
+            [[routes]]
+            decisions_repo = "mozilla/grid-decisions"
+            wg_repo = "w3c/fxtf-drafts"
+            "#,
+        )
+        .unwrap();
+        let route = |labels: &[&str]| config.route("w3c/csswg-drafts", labels, "Title");
+        assert_eq!(route(&["css-grid-2"]), "mozilla/grid-decisions");
+        assert_eq!(route(&["css-flexbox-1"]), "mozilla/css-decisions");
+        assert_eq!(
+            config.decisions_repos(),
+            vec![
+                "mozilla/wg-decisions",
+                "mozilla/grid-decisions",
+                "mozilla/css-decisions",
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_routes_are_rejected() {
+        for route in &[
+            r#"decisions_repo = "wg-decisions""#,
+            r#"decisions_repo = "mozilla/wg decisions""#,
+            "decisions_repo = \"mozilla/wg-decisions\"\nwg_repo = \"csswg-drafts\"",
+            "decisions_repo = \"mozilla/wg-decisions\"\ntitle = \"[css-grid\"",
+        ] {
+            assert!(
+                with_routes(&format!("[[routes]]\n{}", route)).is_err(),
+                "{}",
+                route
+            );
+        }
+    }
+
+    #[test]
+    fn duplicate_wg_repos_are_rejected() {
+        let duplicate = r#"
+            [[wg_repos]]
+            owner = "w3c"
+            name = "csswg-drafts"
+        "#;
+        assert!(Config::from_toml(&format!("{}{}", CONFIG, duplicate)).is_err());
+        assert!(Config::from_toml(&format!(
+            "wg_repo_owner = \"w3c\"\nwg_repo_name = \"fxtf-drafts\"\n{}",
+            CONFIG
+        ))
+        .is_err());
+        assert!(Config::from_toml(&format!(
+            "wg_repo_owner = \"w3c\"\nwg_repo_name = \"svgwg\"\n{}",
+            CONFIG
+        ))
+        .is_ok());
+    }
+
+    #[test]
+    fn wg_repos_are_looked_up_by_id() {
+        let config = with_routes("").unwrap();
+        assert_eq!(
+            config.wg_repo("w3c/fxtf-drafts").unwrap().name,
+            "fxtf-drafts"
+        );
+        assert!(config.wg_repo("w3c/svgwg").is_err());
+    }
+
+    #[test]
+    fn decisions_repo_urls_use_github_endpoint_host() {
+        let url = |endpoint: &str| {
+            Config::from_toml(&format!("github_endpoint = \"{}\"\n{}", endpoint, CONFIG))
+                .unwrap()
+                .decisions_repo_url("mozilla/wg-decisions")
+        };
+        assert_eq!(
+            with_routes("")
+                .unwrap()
+                .decisions_repo_url("mozilla/wg-decisions"),
+            "https://github.com/mozilla/wg-decisions"
+        );
+        assert_eq!(
+            url("https://github.example.com/api/graphql"),
+            "https://github.example.com/mozilla/wg-decisions"
+        );
+        assert_eq!(
+            url("http://127.0.0.1:8080/graphql"),
+            "http://127.0.0.1:8080/mozilla/wg-decisions"
+        );
+        assert!(Config::from_toml(&format!("github_endpoint = \"graphql\"\n{}", CONFIG)).is_err());
+    }
+}
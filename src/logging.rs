@@ -0,0 +1,87 @@
+use chrono::Local;
+use serde_json::{Map, Value};
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+/// How log messages are written.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// One human readable line per message, with fields as `key=value`.
+    #[default]
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static SPANS: RefCell<Vec<Vec<(String, Value)>>> = const { RefCell::new(Vec::new()) };
+}
+
+pub fn set_format(format: LogFormat) {
+    JSON.store(format == LogFormat::Json, Ordering::SeqCst);
+}
+
+/// A set of fields that are added to every message logged on this thread
+/// while it is alive.
+pub struct Span(());
+
+pub fn span(fields: Vec<(String, Value)>) -> Span {
+    SPANS.with(|spans| spans.borrow_mut().push(fields));
+    Span(())
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        SPANS.with(|spans| spans.borrow_mut().pop());
+    }
+}
+
+pub fn info(message: &str, fields: &[(&str, Value)]) {
+    log("info", message, fields);
+}
+
+pub fn warn(message: &str, fields: &[(&str, Value)]) {
+    log("warn", message, fields);
+}
+
+pub fn error(message: &str, fields: &[(&str, Value)]) {
+    log("error", message, fields);
+}
+
+/// Returns the number of milliseconds since `start`, for use as a field.
+pub fn elapsed_ms(start: Instant) -> Value {
+    Value::from(start.elapsed().as_millis() as u64)
+}
+
+fn log(level: &str, message: &str, fields: &[(&str, Value)]) {
+    let time = Local::now();
+    let mut all_fields =
+        SPANS.with(|spans| spans.borrow().iter().flatten().cloned().collect::<Vec<_>>());
+    all_fields.extend(fields.iter().map(|(k, v)| (k.to_string(), v.clone())));
+
+    if JSON.load(Ordering::SeqCst) {
+        let mut object = Map::new();
+        object.insert(String::from("time"), Value::from(time.to_rfc3339()));
+        object.insert(String::from("level"), Value::from(level));
+        object.insert(String::from("message"), Value::from(message));
+        for (k, v) in all_fields {
+            object.insert(k, v);
+        }
+        println!("{}", Value::Object(object));
+    } else {
+        let mut line = format!("[{:?}] ", time);
+        if level != "info" {
+            line.push_str(level);
+            line.push_str(": ");
+        }
+        line.push_str(message);
+        for (k, v) in all_fields {
+            line.push_str(&format!(" {}={}", k, v));
+        }
+        println!("{}", line);
+    }
+}
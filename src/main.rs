@@ -1,37 +1,174 @@
 #[macro_use]
 extern crate serde_derive;
 
+mod client;
 mod config;
+mod logging;
+mod metrics;
 mod query;
 mod repo_config;
+mod resolutions;
 mod state;
 mod tracker;
 mod util;
+mod webhook;
 
-use chrono::Local;
-use clap::{App, Arg};
+#[cfg(test)]
+mod tests;
+
+use clap::{App, AppSettings, Arg, SubCommand};
 use config::Config;
-use failure::Error;
+use failure::{Error, ResultExt};
+use query::IssueKind;
 use tracker::Tracker;
+use util::print_error;
 
 fn run() -> Result<(), Error> {
     let matches = App::new("wg-tracker")
         .arg(Arg::with_name("CONFIG").help("Config file").required(true))
+        .arg(
+            Arg::with_name("dry-run")
+                .long("dry-run")
+                .help("Print mutations instead of performing them, and don't save state"),
+        )
+        .arg(
+            Arg::with_name("migrate-only")
+                .long("migrate-only")
+                .help("Upgrade the state to the current version and exit"),
+        )
+        .arg(
+            Arg::with_name("record")
+                .long("record")
+                .value_name("DIR")
+                .conflicts_with("replay")
+                .help("Save the GitHub and Bugzilla requests and responses in a directory"),
+        )
+        .arg(
+            Arg::with_name("replay")
+                .long("replay")
+                .value_name("DIR")
+                .help(
+                    "Answer GitHub and Bugzilla requests from a --record directory, starting \
+                     from the state recorded there, and don't save state",
+                ),
+        )
+        .subcommand(SubCommand::with_name("run").about("Process updates (the default)"))
+        .subcommand(
+            SubCommand::with_name("daemon").about("Keep running, processing updates periodically"),
+        )
+        .subcommand(
+            SubCommand::with_name("serve").about("Process updates as GitHub webhooks are received"),
+        )
+        .subcommand(SubCommand::with_name("status").about("Show a summary of the state"))
+        .subcommand(
+            SubCommand::with_name("queue")
+                .about("Inspect or modify the task queue")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(SubCommand::with_name("list").about("List queued and dead tasks"))
+                .subcommand(
+                    SubCommand::with_name("drop")
+                        .about("Remove a task from the queue")
+                        .arg(Arg::with_name("INDEX").required(true))
+                        .arg(
+                            Arg::with_name("dead")
+                                .long("dead")
+                                .help("Remove a task from the dead task list instead"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("retry")
+                        .about("Retry dead tasks and tasks waiting to be retried"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("state")
+                .about("Maintain the state")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("gc")
+                        .about("Forget comments and decisions issues handled long ago"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("restore")
+                .about("Replace the state with a snapshot")
+                .arg(
+                    Arg::with_name("SNAPSHOT")
+                        .required(true)
+                        .help("The snapshot's file name, or a path to a state file"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("reprocess")
+                .about("Look at all the comments in a WG issue again")
+                .arg(Arg::with_name("WG_ISSUE_NUMBER").required(true))
+                .arg(
+                    Arg::with_name("kind")
+                        .long("kind")
+                        .possible_values(&["issue", "pull_request", "discussion"])
+                        .default_value("issue")
+                        .help("Whether the number is of an issue, pull request or discussion"),
+                )
+                .arg(
+                    Arg::with_name("repo")
+                        .long("repo")
+                        .value_name("OWNER/NAME")
+                        .help("The WG repository (defaults to the first one configured)"),
+                ),
+        )
         .get_matches();
-    let config = Config::from_file(matches.value_of("CONFIG").unwrap())?;
-    Tracker::new(config).run()?;
+    let config_path = matches.value_of("CONFIG").unwrap();
+    let mut config = Config::from_file(config_path)?;
+    config.dry_run = matches.is_present("dry-run");
+    logging::set_format(config.log_format);
+    if let Some(directory) = matches.value_of("record") {
+        query::record(directory)?;
+    }
+    if let Some(directory) = matches.value_of("replay") {
+        query::replay(directory)?;
+    }
+    let mut tracker = Tracker::new(config);
+    if matches.is_present("migrate-only") {
+        return tracker.migrate();
+    }
+    match matches.subcommand() {
+        ("daemon", _) => tracker.daemon(config_path)?,
+        ("serve", _) => tracker.serve()?,
+        ("status", _) => tracker.status()?,
+        ("queue", Some(matches)) => match matches.subcommand() {
+            ("list", _) => tracker.list_queue()?,
+            ("drop", Some(matches)) => {
+                let index = matches
+                    .value_of("INDEX")
+                    .unwrap()
+                    .parse::<usize>()
+                    .context("could not parse task index")?;
+                tracker.drop_task(index, matches.is_present("dead"))?;
+            }
+            ("retry", _) => tracker.retry_tasks()?,
+            _ => unreachable!(),
+        },
+        ("state", Some(matches)) => match matches.subcommand() {
+            ("gc", _) => tracker.gc()?,
+            _ => unreachable!(),
+        },
+        ("restore", Some(matches)) => tracker.restore(matches.value_of("SNAPSHOT").unwrap())?,
+        ("reprocess", Some(matches)) => {
+            let number = matches
+                .value_of("WG_ISSUE_NUMBER")
+                .unwrap()
+                .parse::<i64>()
+                .context("could not parse WG issue number")?;
+            let kind = matches.value_of("kind").unwrap().parse::<IssueKind>()?;
+            tracker.reprocess(matches.value_of("repo"), kind, number)?;
+        }
+        _ => tracker.run()?,
+    }
     Ok(())
 }
 
 fn main() {
     if let Err(e) = run() {
-        println!(
-            "[{:?}] error: {}{}",
-            Local::now(),
-            e,
-            e.as_fail()
-                .cause()
-                .map_or(String::new(), |f| format!(": {}", f))
-        );
+        print_error(&e);
     }
 }
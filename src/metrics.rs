@@ -0,0 +1,137 @@
+use failure::{format_err, Error, ResultExt};
+use lazy_static::lazy_static;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::thread;
+use tiny_http::{Response, Server};
+
+pub const RESOLUTIONS_DETECTED: &str = "wg_tracker_resolutions_detected_total";
+pub const TRACKING_ISSUES_FILED: &str = "wg_tracker_tracking_issues_filed_total";
+pub const BUGS_FILED: &str = "wg_tracker_bugs_filed_total";
+pub const DECISIONS_ISSUES_CLOSED: &str = "wg_tracker_decisions_issues_closed_total";
+pub const TASK_FAILURES: &str = "wg_tracker_task_failures_total";
+pub const QUEUE_DEPTH: &str = "wg_tracker_queue_depth";
+pub const QUEUE_WAITING: &str = "wg_tracker_queue_waiting_for_retry";
+pub const DEAD_TASKS: &str = "wg_tracker_dead_tasks";
+pub const RATE_LIMIT_REMAINING: &str = "wg_tracker_github_rate_limit_remaining";
+
+/// The name, type and help text of each metric we export.
+const METRICS: &[(&str, &str, &str)] = &[
+    (
+        RESOLUTIONS_DETECTED,
+        "counter",
+        "Resolutions found in WG comments that were not already tracked.",
+    ),
+    (
+        TRACKING_ISSUES_FILED,
+        "counter",
+        "Issues filed in decisions repositories.",
+    ),
+    (BUGS_FILED, "counter", "Bugs filed for decisions issues."),
+    (
+        DECISIONS_ISSUES_CLOSED,
+        "counter",
+        "Decisions issues closed after filing a bug.",
+    ),
+    (TASK_FAILURES, "counter", "Failed task runs, by task type."),
+    (QUEUE_DEPTH, "gauge", "Tasks in the queue."),
+    (
+        QUEUE_WAITING,
+        "gauge",
+        "Tasks in the queue that are waiting to be retried.",
+    ),
+    (
+        DEAD_TASKS,
+        "gauge",
+        "Tasks that failed too many times to be retried.",
+    ),
+    (
+        RATE_LIMIT_REMAINING,
+        "gauge",
+        "GitHub API rate limit points remaining, as of the last request.",
+    ),
+];
+
+lazy_static! {
+    /// The current value of each series, keyed by metric name and labels.
+    static ref VALUES: Mutex<BTreeMap<(&'static str, String), f64>> = Mutex::new(BTreeMap::new());
+}
+
+pub fn inc(name: &'static str, amount: usize) {
+    *VALUES
+        .lock()
+        .unwrap()
+        .entry((name, String::new()))
+        .or_default() += amount as f64;
+}
+
+pub fn inc_labeled(name: &'static str, label: &str, value: &str) {
+    let labels = format!(
+        "{{{}=\"{}\"}}",
+        label,
+        value.replace('\\', "\\\\").replace('"', "\\\"")
+    );
+    *VALUES.lock().unwrap().entry((name, labels)).or_default() += 1.0;
+}
+
+pub fn set(name: &'static str, value: f64) {
+    VALUES.lock().unwrap().insert((name, String::new()), value);
+}
+
+/// Returns all metrics in the Prometheus text exposition format.
+pub fn render() -> String {
+    let values = VALUES.lock().unwrap();
+    let mut output = String::new();
+    for (name, kind, help) in METRICS {
+        let _ = writeln!(output, "# HELP {} {}", name, help);
+        let _ = writeln!(output, "# TYPE {} {}", name, kind);
+        let series = values
+            .range((*name, String::new())..)
+            .take_while(|((n, _), _)| n == name)
+            .collect::<Vec<_>>();
+        for ((_, labels), value) in &series {
+            let _ = writeln!(output, "{}{} {}", name, labels, value);
+        }
+        if series.is_empty() && *kind == "counter" {
+            let _ = writeln!(output, "{} 0", name);
+        }
+    }
+    output
+}
+
+/// Writes the metrics to a file in `directory` for node_exporter's textfile
+/// collector to pick up.
+pub fn write_textfile(directory: &str) -> Result<(), Error> {
+    let path = Path::new(directory).join("wg_tracker.prom");
+    let temp_path = Path::new(directory).join("wg_tracker.prom.temp");
+    {
+        let mut file = File::create(&temp_path).context("could not create metrics file")?;
+        file.write_all(render().as_bytes())
+            .context("could not write metrics file")?;
+    }
+    fs::rename(&temp_path, &path).context("could not write metrics file")?;
+    Ok(())
+}
+
+/// Starts a thread that serves the metrics at `/metrics` on the given
+/// address.
+pub fn serve(address: &str) -> Result<(), Error> {
+    let server = Server::http(address)
+        .map_err(|e| format_err!("could not start metrics server on {}: {}", address, e))?;
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let response = if request.url() == "/metrics" {
+                Response::from_string(render())
+            } else {
+                Response::from_string("not found").with_status_code(404)
+            };
+            // Nothing useful can be done if the client has gone away.
+            let _ = request.respond(response);
+        }
+    });
+    Ok(())
+}
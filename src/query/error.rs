@@ -0,0 +1,81 @@
+use failure::Fail;
+
+/// An error from a GitHub or Bugzilla request.
+#[derive(Debug, Fail)]
+pub enum QueryError {
+    #[fail(display = "could not perform network request")]
+    Network(#[cause] reqwest::Error),
+    #[fail(display = "could not read response body")]
+    Body(#[cause] reqwest::Error),
+    #[fail(display = "could not parse response")]
+    Parse(#[cause] serde_json::Error),
+    #[fail(display = "request was not authorized (bad token?)")]
+    Unauthorized,
+    #[fail(display = "rate limit exceeded")]
+    RateLimited,
+    #[fail(display = "request failed with HTTP status {}", _0)]
+    HttpStatus(u16),
+    #[fail(display = "not found: {}", _0)]
+    NotFound(String),
+    #[fail(display = "errors in response: {:?}", _0)]
+    GraphQL(Vec<GraphQLError>),
+    #[fail(display = "{}", _0)]
+    MissingData(&'static str),
+    #[fail(display = "bugzilla error {:?}: {}", code, message)]
+    Bugzilla { code: Option<i64>, message: String },
+}
+
+/// Bugzilla error codes for bugs that were rejected as invalid, such as for
+/// having an unknown product or component.
+/// https://bmo.readthedocs.io/en/latest/api/core/v1/bug.html#create-bug
+const BUGZILLA_INVALID_BUG_CODES: &[i64] = &[51, 103, 104, 105, 106, 107, 116, 120, 504];
+
+/// An entry in the `errors` array of a GitHub GraphQL response.
+#[derive(Clone, Debug, Deserialize)]
+pub struct GraphQLError {
+    pub message: String,
+    #[serde(rename = "type")]
+    pub error_type: Option<String>,
+}
+
+impl QueryError {
+    pub fn from_graphql_errors(errors: Vec<GraphQLError>) -> QueryError {
+        let has_type = |t| errors.iter().any(|e| e.error_type.as_deref() == Some(t));
+        if has_type("RATE_LIMITED") {
+            QueryError::RateLimited
+        } else if has_type("NOT_FOUND") {
+            QueryError::NotFound(
+                errors
+                    .into_iter()
+                    .map(|e| e.message)
+                    .collect::<Vec<_>>()
+                    .join("; "),
+            )
+        } else {
+            QueryError::GraphQL(errors)
+        }
+    }
+
+    /// Returns whether the error will not go away by itself, or not before
+    /// the rate limit is reset, and so the whole run should be stopped.
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, QueryError::Unauthorized | QueryError::RateLimited)
+    }
+
+    /// Returns whether retrying the request is pointless, since it will
+    /// just fail in the same way again.  Other GraphQL errors, such as
+    /// "Something went wrong while executing your query", are often
+    /// transient.
+    pub fn is_permanent(&self) -> bool {
+        match self {
+            QueryError::NotFound(_) => true,
+            QueryError::GraphQL(errors) => errors
+                .iter()
+                .any(|e| e.error_type.as_deref() == Some("FORBIDDEN")),
+            QueryError::Bugzilla {
+                code: Some(code), ..
+            } => BUGZILLA_INVALID_BUG_CODES.contains(code),
+            _ => false,
+        }
+    }
+}
@@ -1,11 +1,91 @@
+mod error;
+mod recording;
+
+use crate::client::{BugTracker, GitHubClient};
+use crate::config::{Config, PageSizes};
+use crate::logging;
+use crate::metrics;
 use crate::util::CLIENT;
-use failure::{format_err, Error, ResultExt};
+use failure::{format_err, Error, Fail, ResultExt};
 use graphql_client::*;
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::cell::Cell;
+use std::str::FromStr;
+use std::time::Instant;
+
+pub use error::{GraphQLError, QueryError};
+#[cfg(test)]
+pub use recording::unreplayed;
+pub use recording::{record, recorded_state_path, replay, replayed_state_path};
 
 type DateTime = String;
 type URI = String;
 
+/// The GitHub API rate limit status, as of the most recent request.
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimit {
+    pub remaining: i64,
+    /// When the rate limit will be reset, in seconds since the Unix epoch.
+    pub reset_at: i64,
+}
+
+/// Returns the GitHub API rate limit status reported by the most recent
+/// request made on this thread, if any.
+pub fn rate_limit() -> Option<RateLimit> {
+    RATE_LIMIT.with(Cell::get)
+}
+
+fn record_rate_limit(response: &reqwest::Response) -> Option<RateLimit> {
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<i64>().ok())
+    };
+
+    let rate_limit = match (header("X-RateLimit-Remaining"), header("X-RateLimit-Reset")) {
+        (Some(remaining), Some(reset_at)) => RateLimit {
+            remaining,
+            reset_at,
+        },
+        _ => return None,
+    };
+
+    RATE_LIMIT.with(|r| r.set(Some(rate_limit)));
+    metrics::set(metrics::RATE_LIMIT_REMAINING, rate_limit.remaining as f64);
+    Some(rate_limit)
+}
+
+/// Logs a completed HTTP request.
+fn log_request(url: &str, operation: &str, start: Instant, response: &reqwest::Response) {
+    let request_id = response
+        .headers()
+        .get("X-GitHub-Request-Id")
+        .and_then(|v| v.to_str().ok());
+    logging::info(
+        "http request",
+        &[
+            ("url", url.into()),
+            ("operation", operation.into()),
+            ("status", response.status().as_u16().into()),
+            ("request_id", request_id.map_or(Value::Null, Value::from)),
+            ("duration_ms", logging::elapsed_ms(start)),
+        ],
+    );
+}
+
+/// A GitHub GraphQL response.  This is used instead of graphql_client's
+/// `Response` so that we can see the GitHub-specific error types.
+#[derive(Deserialize)]
+struct GitHubResponse<Data> {
+    data: Option<Data>,
+    errors: Option<Vec<GraphQLError>>,
+}
+
 fn do_perform_query<Q>(
+    endpoint: &str,
     token: &str,
     mime_type: Option<&str>,
     variables: Q::Variables,
@@ -13,36 +93,63 @@ fn do_perform_query<Q>(
 where
     Q: GraphQLQuery,
 {
-    let mut request = CLIENT.post(GITHUB_ENDPOINT).bearer_auth(token);
+    let mut request = CLIENT.post(endpoint).bearer_auth(token);
 
     if let Some(mime_type) = mime_type {
         request = request.header("Accept", mime_type);
     }
 
-    let response = request
-        .json(&Q::build_query(variables))
-        .send()
-        .context("could not perform network request")?
-        .json::<Response<Q::ResponseData>>()
-        .context("could not parse response")?;
+    let query = Q::build_query(variables);
+    let recorded_request =
+        serde_json::to_value(&query.variables).context("could not serialize variables")?;
+    let mut rate_limit = None;
+    let (status, response_string) =
+        recording::exchange(endpoint, query.operation_name, &recorded_request, || {
+            let start = Instant::now();
+            let mut response = request.json(&query).send().map_err(QueryError::Network)?;
+            log_request(endpoint, query.operation_name, start, &response);
+            rate_limit = record_rate_limit(&response);
+            let text = response.text().map_err(QueryError::Body)?;
+            Ok((response.status(), text))
+        })?;
+
+    match status {
+        StatusCode::UNAUTHORIZED => return Err(QueryError::Unauthorized.into()),
+        StatusCode::FORBIDDEN | StatusCode::TOO_MANY_REQUESTS if matches!(rate_limit, Some(r) if r.remaining == 0) =>
+        {
+            return Err(QueryError::RateLimited.into());
+        }
+        status if !status.is_success() => {
+            return Err(QueryError::HttpStatus(status.as_u16()).into());
+        }
+        _ => {}
+    }
+
+    let response = serde_json::from_str::<GitHubResponse<Q::ResponseData>>(&response_string)
+        .map_err(QueryError::Parse)?;
 
     if let Some(errors) = response.errors {
-        return Err(format_err!("errors in response: {:?}", errors));
+        return Err(QueryError::from_graphql_errors(errors).into());
     }
 
-    response
+    Ok(response
         .data
-        .ok_or_else(|| format_err!("no data in response"))
+        .ok_or(QueryError::MissingData("no data in response"))?)
 }
 
-fn perform_query<Q>(token: &str, variables: Q::Variables) -> Result<Q::ResponseData, Error>
+fn perform_query<Q>(
+    endpoint: &str,
+    token: &str,
+    variables: Q::Variables,
+) -> Result<Q::ResponseData, Error>
 where
     Q: GraphQLQuery,
 {
-    do_perform_query::<Q>(token, None, variables)
+    do_perform_query::<Q>(endpoint, token, None, variables)
 }
 
 fn perform_query_with_preview<Q>(
+    endpoint: &str,
     token: &str,
     mime_type: &str,
     variables: Q::Variables,
@@ -50,24 +157,41 @@ fn perform_query_with_preview<Q>(
 where
     Q: GraphQLQuery,
 {
-    do_perform_query::<Q>(token, Some(mime_type), variables)
+    do_perform_query::<Q>(endpoint, token, Some(mime_type), variables)
+}
+
+/// Prints the variables of a mutation that would have been sent, for use in
+/// dry-run mode.
+fn print_dry_run_mutation<Q>(variables: Q::Variables) -> Result<(), Error>
+where
+    Q: GraphQLQuery,
+{
+    let query = Q::build_query(variables);
+    println!(
+        "[dry run] {} {}",
+        query.operation_name,
+        serde_json::to_string_pretty(&query.variables).context("could not serialize variables")?
+    );
+    Ok(())
+}
+
+/// The `pageInfo` of a page of results, when paginating forwards.
+struct PageInfo {
+    has_next_page: bool,
+    end_cursor: Option<String>,
 }
 
 trait PaginatedQueryBase: GraphQLQuery {
-    type Edge: EdgeCursor;
+    type Edge;
     type Item;
 
-    fn get_total_and_edges(data: Self::ResponseData) -> Option<(i64, Vec<Option<Self::Edge>>)>;
+    fn get_page(data: Self::ResponseData) -> Option<(PageInfo, Vec<Option<Self::Edge>>)>;
 }
 
 trait PaginatedQuery: PaginatedQueryBase {
     fn make_item(edge: Self::Edge) -> Option<Self::Item>;
 }
 
-trait EdgeCursor {
-    fn cursor(&self) -> String;
-}
-
 trait PaginatedQueryVariables {
     fn clone_with_after(&self, after: Option<String>) -> Self;
 }
@@ -79,12 +203,6 @@ macro_rules! paginated_query {
         edges => $edges:ty,
         path => ($($path:tt)+),
     ) => {
-        impl EdgeCursor for $edges {
-            fn cursor(&self) -> String {
-                self.cursor.clone()
-            }
-        }
-
         impl PaginatedQueryVariables for <$ty as GraphQLQuery>::Variables {
             fn clone_with_after(&self, after: Option<String>) -> Self {
                 let mut v = self.clone();
@@ -97,37 +215,66 @@ macro_rules! paginated_query {
             type Edge = $edges;
             type Item = $item;
 
-            fn get_total_and_edges(
+            fn get_page(
                 data: <Self as GraphQLQuery>::ResponseData,
-            ) -> Option<(i64, Vec<Option<Self::Edge>>)> {
+            ) -> Option<(PageInfo, Vec<Option<Self::Edge>>)> {
                 let items = data.$($path)+;
-                Some((items.total_count, items.edges?))
+                let page_info = PageInfo {
+                    has_next_page: items.page_info.has_next_page,
+                    end_cursor: items.page_info.end_cursor,
+                };
+                Some((page_info, items.edges?))
             }
         }
     };
 }
 
-fn perform_paginated_query<P>(token: &str, variables: P::Variables) -> Result<Vec<P::Item>, Error>
+/// Performs a query for each page of results in turn, following the
+/// `endCursor` of each page until there are no more.  Null nodes are
+/// skipped.
+fn perform_paginated_query<P>(
+    endpoint: &str,
+    token: &str,
+    variables: P::Variables,
+) -> Result<Vec<P::Item>, Error>
+where
+    P: PaginatedQuery,
+    P::Variables: PaginatedQueryVariables,
+{
+    perform_paginated_query_while::<P, _>(endpoint, token, variables, |_| true)
+}
+
+/// Like `perform_paginated_query`, but stops at the first item for which
+/// `wanted` returns false, for queries whose results are ordered such that
+/// no later items will be wanted either.
+fn perform_paginated_query_while<P, F>(
+    endpoint: &str,
+    token: &str,
+    variables: P::Variables,
+    wanted: F,
+) -> Result<Vec<P::Item>, Error>
 where
     P: PaginatedQuery,
     P::Variables: PaginatedQueryVariables,
+    F: Fn(&P::Item) -> bool,
 {
     let mut result = Vec::new();
     let mut after = None;
-    let mut total_count;
 
     loop {
-        let response_data = perform_query::<P>(token, variables.clone_with_after(after))?;
-        let (count, edges) = P::get_total_and_edges(response_data)
-            .ok_or_else(|| format_err!("error parsing paginated query response"))?;
-        if edges.is_empty() {
-            break;
+        let response_data = perform_query::<P>(endpoint, token, variables.clone_with_after(after))?;
+        let (page_info, edges) = P::get_page(response_data).ok_or(QueryError::MissingData(
+            "error parsing paginated query response",
+        ))?;
+        for item in edges.into_iter().flatten().flat_map(P::make_item) {
+            if !wanted(&item) {
+                return Ok(result);
+            }
+            result.push(item);
         }
-        total_count = count;
-        after = edges.last().unwrap().as_ref().map(|e| e.cursor());
-        result.extend(edges.into_iter().flatten().map(P::make_item).flatten());
-        if result.len() >= total_count as usize {
-            break;
+        match page_info.end_cursor {
+            Some(cursor) if page_info.has_next_page => after = Some(cursor),
+            _ => break,
         }
     }
 
@@ -148,9 +295,63 @@ pub struct UpdatedIssue {
     pub issue_number: i64,
     pub issue_title: String,
     pub updated_at: String,
+    pub closed: bool,
     pub issue_labels: Vec<IssueLabel>,
 }
 
+/// The kinds of WG item whose comments we look for resolutions in.  Items are
+/// identified by their kind and number together, since discussions are
+/// numbered separately from issues and pull requests.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueKind {
+    #[default]
+    Issue,
+    PullRequest,
+    Discussion,
+}
+
+impl IssueKind {
+    pub const ALL: &'static [IssueKind] = &[
+        IssueKind::Issue,
+        IssueKind::PullRequest,
+        IssueKind::Discussion,
+    ];
+
+    /// Returns the name of the kind, as used in state files and on the
+    /// command line.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            IssueKind::Issue => "issue",
+            IssueKind::PullRequest => "pull_request",
+            IssueKind::Discussion => "discussion",
+        }
+    }
+
+    /// Returns the URL of the item of this kind with the given number, in the
+    /// repository with the given URL.
+    pub fn url(self, repo_url: &str, number: i64) -> String {
+        let path = match self {
+            IssueKind::Issue => "issues",
+            IssueKind::PullRequest => "pull",
+            IssueKind::Discussion => "discussions",
+        };
+        format!("{}/{}/{}", repo_url, path, number)
+    }
+}
+
+impl FromStr for IssueKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<IssueKind, Error> {
+        IssueKind::ALL
+            .iter()
+            .copied()
+            .find(|kind| kind.as_str() == s)
+            .ok_or_else(|| format_err!("unknown kind of WG item '{}'", s))
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct IssueLabel {
     pub name: String,
@@ -171,6 +372,7 @@ impl PaginatedQuery for UpdatedIssues {
             issue_number: issue.number,
             issue_title: issue.title,
             updated_at: issue.updated_at,
+            closed: issue.closed,
             issue_labels: {
                 issue
                     .labels
@@ -188,18 +390,22 @@ impl PaginatedQuery for UpdatedIssues {
     }
 }
 
-pub fn updated_issues(
+fn updated_issues(
+    endpoint: &str,
     token: &str,
     wg_repo_owner: &str,
     wg_repo_name: &str,
     since: &str,
+    page_size: i64,
 ) -> Result<Vec<UpdatedIssue>, Error> {
     perform_paginated_query::<UpdatedIssues>(
+        endpoint,
         token,
         updated_issues::Variables {
             repo_owner: wg_repo_owner.to_string(),
             repo_name: wg_repo_name.to_string(),
             since: since.to_string(),
+            page_size,
             after: None,
         },
     )
@@ -208,50 +414,487 @@ pub fn updated_issues(
 #[derive(GraphQLQuery)]
 #[graphql(
     schema_path = "src/github_schema.graphql",
-    query_path = "src/query/issue_comments.graphql",
+    query_path = "src/query/updated_pull_requests.graphql",
     response_derives = "Clone, Debug"
 )]
-struct IssueComments;
+struct UpdatedPullRequests;
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
-pub struct IssueComment {
-    pub url: String,
-    pub created_at: String,
-    pub body_text: String,
+paginated_query! {
+    query => UpdatedPullRequests,
+    item => UpdatedIssue,
+    edges => updated_pull_requests::UpdatedPullRequestsRepositoryPullRequestsEdges,
+    path => (repository?.pull_requests),
+}
+
+impl PaginatedQuery for UpdatedPullRequests {
+    fn make_item(edge: Self::Edge) -> Option<Self::Item> {
+        edge.node.map(|pull_request| UpdatedIssue {
+            id: pull_request.id,
+            issue_number: pull_request.number,
+            issue_title: pull_request.title,
+            updated_at: pull_request.updated_at,
+            closed: pull_request.closed,
+            issue_labels: {
+                pull_request
+                    .labels
+                    .and_then(|x| x.edges)
+                    .into_iter()
+                    .flatten()
+                    .flat_map(|e| e?.node)
+                    .map(|label| IssueLabel {
+                        name: label.name,
+                        color: label.color,
+                    })
+                    .collect()
+            },
+        })
+    }
+}
+
+/// Returns the pull requests updated since the given time, oldest first.
+/// Pull requests can't be filtered by when they were updated, so we fetch
+/// them newest first until we reach one that is older.
+fn updated_pull_requests(
+    endpoint: &str,
+    token: &str,
+    wg_repo_owner: &str,
+    wg_repo_name: &str,
+    since: &str,
+    page_size: i64,
+) -> Result<Vec<UpdatedIssue>, Error> {
+    let mut result = perform_paginated_query_while::<UpdatedPullRequests, _>(
+        endpoint,
+        token,
+        updated_pull_requests::Variables {
+            repo_owner: wg_repo_owner.to_string(),
+            repo_name: wg_repo_name.to_string(),
+            page_size,
+            after: None,
+        },
+        |pull_request| pull_request.updated_at.as_str() >= since,
+    )?;
+    result.reverse();
+    Ok(result)
 }
 
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/github_schema.graphql",
+    query_path = "src/query/updated_discussions.graphql",
+    response_derives = "Clone, Debug"
+)]
+struct UpdatedDiscussions;
+
 paginated_query! {
-    query => IssueComments,
-    item => IssueComment,
-    edges => issue_comments::IssueCommentsRepositoryIssueCommentsEdges,
-    path => (repository?.issue?.comments),
+    query => UpdatedDiscussions,
+    item => UpdatedIssue,
+    edges => updated_discussions::UpdatedDiscussionsRepositoryDiscussionsEdges,
+    path => (repository?.discussions),
 }
 
-impl PaginatedQuery for IssueComments {
+impl PaginatedQuery for UpdatedDiscussions {
     fn make_item(edge: Self::Edge) -> Option<Self::Item> {
-        edge.node.map(|n| IssueComment {
-            created_at: n.created_at,
-            url: n.url,
-            body_text: n.body_text,
+        edge.node.map(|discussion| UpdatedIssue {
+            id: discussion.id,
+            issue_number: discussion.number,
+            issue_title: discussion.title,
+            updated_at: discussion.updated_at,
+            closed: discussion.closed,
+            issue_labels: {
+                discussion
+                    .labels
+                    .and_then(|x| x.edges)
+                    .into_iter()
+                    .flatten()
+                    .flat_map(|e| e?.node)
+                    .map(|label| IssueLabel {
+                        name: label.name,
+                        color: label.color,
+                    })
+                    .collect()
+            },
         })
     }
 }
 
-pub fn issue_comments(
+/// Returns the discussions updated since the given time, oldest first, in
+/// the same way as `updated_pull_requests`.
+fn updated_discussions(
+    endpoint: &str,
     token: &str,
     wg_repo_owner: &str,
     wg_repo_name: &str,
-    number: i64,
-) -> Result<Vec<IssueComment>, Error> {
-    perform_paginated_query::<IssueComments>(
+    since: &str,
+    page_size: i64,
+) -> Result<Vec<UpdatedIssue>, Error> {
+    let mut result = perform_paginated_query_while::<UpdatedDiscussions, _>(
+        endpoint,
         token,
-        issue_comments::Variables {
+        updated_discussions::Variables {
             repo_owner: wg_repo_owner.to_string(),
             repo_name: wg_repo_name.to_string(),
-            number,
+            page_size,
             after: None,
         },
-    )
+        |discussion| discussion.updated_at.as_str() >= since,
+    )?;
+    result.reverse();
+    Ok(result)
+}
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/github_schema.graphql",
+    query_path = "src/query/issue.graphql",
+    response_derives = "Debug"
+)]
+struct Issue;
+
+fn issue(
+    endpoint: &str,
+    token: &str,
+    repo_owner: &str,
+    repo_name: &str,
+    number: i64,
+) -> Result<UpdatedIssue, Error> {
+    let data = perform_query::<Issue>(
+        endpoint,
+        token,
+        issue::Variables {
+            repo_owner: repo_owner.to_string(),
+            repo_name: repo_name.to_string(),
+            number,
+        },
+    )?;
+
+    let issue = data
+        .repository
+        .and_then(|r| r.issue)
+        .ok_or_else(|| QueryError::NotFound(format!("issue {}", number)))?;
+
+    Ok(UpdatedIssue {
+        id: issue.id,
+        issue_number: issue.number,
+        issue_title: issue.title,
+        updated_at: issue.updated_at,
+        closed: issue.closed,
+        issue_labels: issue
+            .labels
+            .and_then(|x| x.edges)
+            .into_iter()
+            .flatten()
+            .flat_map(|e| e?.node)
+            .map(|label| IssueLabel {
+                name: label.name,
+                color: label.color,
+            })
+            .collect(),
+    })
+}
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/github_schema.graphql",
+    query_path = "src/query/pull_request.graphql",
+    response_derives = "Debug"
+)]
+struct PullRequest;
+
+fn pull_request(
+    endpoint: &str,
+    token: &str,
+    repo_owner: &str,
+    repo_name: &str,
+    number: i64,
+) -> Result<UpdatedIssue, Error> {
+    let data = perform_query::<PullRequest>(
+        endpoint,
+        token,
+        pull_request::Variables {
+            repo_owner: repo_owner.to_string(),
+            repo_name: repo_name.to_string(),
+            number,
+        },
+    )?;
+
+    let issue = data
+        .repository
+        .and_then(|r| r.pull_request)
+        .ok_or_else(|| QueryError::NotFound(format!("pull request {}", number)))?;
+
+    Ok(UpdatedIssue {
+        id: issue.id,
+        issue_number: issue.number,
+        issue_title: issue.title,
+        updated_at: issue.updated_at,
+        closed: issue.closed,
+        issue_labels: issue
+            .labels
+            .and_then(|x| x.edges)
+            .into_iter()
+            .flatten()
+            .flat_map(|e| e?.node)
+            .map(|label| IssueLabel {
+                name: label.name,
+                color: label.color,
+            })
+            .collect(),
+    })
+}
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/github_schema.graphql",
+    query_path = "src/query/discussion.graphql",
+    response_derives = "Debug"
+)]
+struct Discussion;
+
+fn discussion(
+    endpoint: &str,
+    token: &str,
+    repo_owner: &str,
+    repo_name: &str,
+    number: i64,
+) -> Result<UpdatedIssue, Error> {
+    let data = perform_query::<Discussion>(
+        endpoint,
+        token,
+        discussion::Variables {
+            repo_owner: repo_owner.to_string(),
+            repo_name: repo_name.to_string(),
+            number,
+        },
+    )?;
+
+    let issue = data
+        .repository
+        .and_then(|r| r.discussion)
+        .ok_or_else(|| QueryError::NotFound(format!("discussion {}", number)))?;
+
+    Ok(UpdatedIssue {
+        id: issue.id,
+        issue_number: issue.number,
+        issue_title: issue.title,
+        updated_at: issue.updated_at,
+        closed: issue.closed,
+        issue_labels: issue
+            .labels
+            .and_then(|x| x.edges)
+            .into_iter()
+            .flatten()
+            .flat_map(|e| e?.node)
+            .map(|label| IssueLabel {
+                name: label.name,
+                color: label.color,
+            })
+            .collect(),
+    })
+}
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/github_schema.graphql",
+    query_path = "src/query/issue_comments.graphql",
+    response_derives = "Clone, Debug"
+)]
+struct IssueComments;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct IssueComment {
+    pub url: String,
+    pub created_at: String,
+    pub last_edited_at: Option<String>,
+    pub body_text: String,
+}
+
+/// Returns the comments on an issue, pull request or discussion, oldest
+/// first.  `fetch_page` is called with the cursor to fetch the page before,
+/// and returns that page's comments along with its own cursor if there are
+/// earlier pages.
+///
+/// Every page is fetched, newest first, even for busy issues: a comment on
+/// any page may have been edited to add a resolution since we last looked,
+/// and there's no way to ask GitHub for only the comments edited since a
+/// given time.
+fn all_comments<F>(mut fetch_page: F) -> Result<Vec<IssueComment>, Error>
+where
+    F: FnMut(Option<String>) -> Result<(Option<String>, Vec<IssueComment>), Error>,
+{
+    let mut result = Vec::new();
+    let mut before = None;
+
+    loop {
+        let (previous_page, page) = fetch_page(before)?;
+        result.splice(0..0, page);
+        match previous_page {
+            Some(cursor) => before = Some(cursor),
+            None => break,
+        }
+    }
+
+    Ok(result)
+}
+
+fn issue_comments(
+    endpoint: &str,
+    token: &str,
+    wg_repo_owner: &str,
+    wg_repo_name: &str,
+    number: i64,
+    page_size: i64,
+) -> Result<Vec<IssueComment>, Error> {
+    all_comments(|before| {
+        let data = perform_query::<IssueComments>(
+            endpoint,
+            token,
+            issue_comments::Variables {
+                repo_owner: wg_repo_owner.to_string(),
+                repo_name: wg_repo_name.to_string(),
+                number,
+                page_size,
+                before,
+            },
+        )?;
+        let comments = data
+            .repository
+            .and_then(|r| r.issue)
+            .map(|i| i.comments)
+            .ok_or_else(|| QueryError::NotFound(format!("issue {}", number)))?;
+        let has_previous_page = comments.page_info.has_previous_page;
+        let previous_page = comments
+            .page_info
+            .start_cursor
+            .filter(|_| has_previous_page);
+        let page = comments
+            .edges
+            .into_iter()
+            .flatten()
+            .flatten()
+            .flat_map(|e| e.node)
+            .map(|n| IssueComment {
+                created_at: n.created_at,
+                last_edited_at: n.last_edited_at,
+                url: n.url,
+                body_text: n.body_text,
+            })
+            .collect();
+        Ok((previous_page, page))
+    })
+}
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/github_schema.graphql",
+    query_path = "src/query/pull_request_comments.graphql",
+    response_derives = "Clone, Debug"
+)]
+struct PullRequestComments;
+
+/// Returns the comments on a pull request, oldest first, as for
+/// `issue_comments`.  Review comments are not included.
+fn pull_request_comments(
+    endpoint: &str,
+    token: &str,
+    wg_repo_owner: &str,
+    wg_repo_name: &str,
+    number: i64,
+    page_size: i64,
+) -> Result<Vec<IssueComment>, Error> {
+    all_comments(|before| {
+        let data = perform_query::<PullRequestComments>(
+            endpoint,
+            token,
+            pull_request_comments::Variables {
+                repo_owner: wg_repo_owner.to_string(),
+                repo_name: wg_repo_name.to_string(),
+                number,
+                page_size,
+                before,
+            },
+        )?;
+        let comments = data
+            .repository
+            .and_then(|r| r.pull_request)
+            .map(|p| p.comments)
+            .ok_or_else(|| QueryError::NotFound(format!("pull request {}", number)))?;
+        let has_previous_page = comments.page_info.has_previous_page;
+        let previous_page = comments
+            .page_info
+            .start_cursor
+            .filter(|_| has_previous_page);
+        let page = comments
+            .edges
+            .into_iter()
+            .flatten()
+            .flatten()
+            .flat_map(|e| e.node)
+            .map(|n| IssueComment {
+                created_at: n.created_at,
+                last_edited_at: n.last_edited_at,
+                url: n.url,
+                body_text: n.body_text,
+            })
+            .collect();
+        Ok((previous_page, page))
+    })
+}
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/github_schema.graphql",
+    query_path = "src/query/discussion_comments.graphql",
+    response_derives = "Clone, Debug"
+)]
+struct DiscussionComments;
+
+/// Returns the top-level comments on a discussion, oldest first, as for
+/// `issue_comments`.  Replies to comments are not included.
+fn discussion_comments(
+    endpoint: &str,
+    token: &str,
+    wg_repo_owner: &str,
+    wg_repo_name: &str,
+    number: i64,
+    page_size: i64,
+) -> Result<Vec<IssueComment>, Error> {
+    all_comments(|before| {
+        let data = perform_query::<DiscussionComments>(
+            endpoint,
+            token,
+            discussion_comments::Variables {
+                repo_owner: wg_repo_owner.to_string(),
+                repo_name: wg_repo_name.to_string(),
+                number,
+                page_size,
+                before,
+            },
+        )?;
+        let comments = data
+            .repository
+            .and_then(|r| r.discussion)
+            .map(|d| d.comments)
+            .ok_or_else(|| QueryError::NotFound(format!("discussion {}", number)))?;
+        let has_previous_page = comments.page_info.has_previous_page;
+        let previous_page = comments
+            .page_info
+            .start_cursor
+            .filter(|_| has_previous_page);
+        let page = comments
+            .edges
+            .into_iter()
+            .flatten()
+            .flatten()
+            .flat_map(|e| e.node)
+            .map(|n| IssueComment {
+                created_at: n.created_at,
+                last_edited_at: n.last_edited_at,
+                url: n.url,
+                body_text: n.body_text,
+            })
+            .collect();
+        Ok((previous_page, page))
+    })
 }
 
 #[derive(GraphQLQuery)]
@@ -284,16 +927,20 @@ impl PaginatedQuery for KnownLabels {
     }
 }
 
-pub fn known_labels(
+fn known_labels(
+    endpoint: &str,
     token: &str,
     repo_owner: &str,
     repo_name: &str,
+    page_size: i64,
 ) -> Result<Vec<KnownLabel>, Error> {
     perform_paginated_query::<KnownLabels>(
+        endpoint,
         token,
         known_labels::Variables {
             repo_owner: repo_owner.to_string(),
             repo_name: repo_name.to_string(),
+            page_size,
             after: None,
         },
     )
@@ -307,8 +954,14 @@ pub fn known_labels(
 )]
 struct RepoID;
 
-pub fn repo_id(token: &str, repo_owner: &str, repo_name: &str) -> Result<Option<String>, Error> {
+fn repo_id(
+    endpoint: &str,
+    token: &str,
+    repo_owner: &str,
+    repo_name: &str,
+) -> Result<Option<String>, Error> {
     let data = perform_query::<RepoID>(
+        endpoint,
         token,
         repo_id::Variables {
             repo_owner: repo_owner.to_string(),
@@ -327,21 +980,36 @@ pub fn repo_id(token: &str, repo_owner: &str, repo_name: &str) -> Result<Option<
 )]
 struct CreateLabel;
 
-pub fn create_label(token: &str, repo_id: &str, name: &str, color: &str) -> Result<String, Error> {
+fn create_label(
+    endpoint: &str,
+    token: &str,
+    repo_id: &str,
+    name: &str,
+    color: &str,
+    dry_run: bool,
+) -> Result<String, Error> {
+    let variables = create_label::Variables {
+        repo_id: repo_id.to_string(),
+        name: name.to_string(),
+        color: color.to_string(),
+    };
+
+    if dry_run {
+        print_dry_run_mutation::<CreateLabel>(variables)?;
+        return Ok(String::from(DRY_RUN_ID));
+    }
+
     let data = perform_query_with_preview::<CreateLabel>(
+        endpoint,
         token,
         "application/vnd.github.bane-preview+json",
-        create_label::Variables {
-            repo_id: repo_id.to_string(),
-            name: name.to_string(),
-            color: color.to_string(),
-        },
+        variables,
     )?;
 
     data.create_label
         .and_then(|m| m.label)
         .map(|l| l.id)
-        .ok_or_else(|| format_err!("label creation failed"))
+        .ok_or_else(|| QueryError::MissingData("label creation failed").into())
 }
 
 #[derive(GraphQLQuery)]
@@ -352,27 +1020,99 @@ pub fn create_label(token: &str, repo_id: &str, name: &str, color: &str) -> Resu
 )]
 struct CreateIssue;
 
-pub fn create_issue(
+#[derive(Clone, Debug)]
+pub struct CreatedIssue {
+    pub id: String,
+    pub number: i64,
+}
+
+fn create_issue(
+    endpoint: &str,
     token: &str,
     repo_id: &str,
     title: String,
     body: Option<String>,
     labels: Option<Vec<String>>,
-) -> Result<String, Error> {
-    let data = perform_query::<CreateIssue>(
+    dry_run: bool,
+) -> Result<CreatedIssue, Error> {
+    let variables = create_issue::Variables {
+        repo_id: repo_id.to_string(),
+        title,
+        body,
+        labels,
+    };
+
+    if dry_run {
+        print_dry_run_mutation::<CreateIssue>(variables)?;
+        return Ok(CreatedIssue {
+            id: String::from(DRY_RUN_ID),
+            number: 0,
+        });
+    }
+
+    let data = perform_query::<CreateIssue>(endpoint, token, variables)?;
+
+    data.create_issue
+        .and_then(|m| m.issue)
+        .map(|i| CreatedIssue {
+            id: i.id,
+            number: i.number,
+        })
+        .ok_or_else(|| QueryError::MissingData("issue creation failed").into())
+}
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/github_schema.graphql",
+    query_path = "src/query/search_issues.graphql",
+    response_derives = "Debug"
+)]
+struct SearchIssues;
+
+#[derive(Clone, Debug)]
+pub struct FoundIssue {
+    pub id: String,
+    pub number: i64,
+    pub body: String,
+}
+
+/// Searches for issues in a repository whose body matches the given text.
+/// GitHub's search is fuzzy, so callers should check the returned issue
+/// bodies themselves.
+fn search_issues(
+    endpoint: &str,
+    token: &str,
+    repo_owner: &str,
+    repo_name: &str,
+    text: &str,
+) -> Result<Vec<FoundIssue>, Error> {
+    let data = perform_query::<SearchIssues>(
+        endpoint,
         token,
-        create_issue::Variables {
-            repo_id: repo_id.to_string(),
-            title,
-            body,
-            labels,
+        search_issues::Variables {
+            query: format!(
+                "repo:{}/{} is:issue in:body \"{}\"",
+                repo_owner, repo_name, text
+            ),
         },
     )?;
 
-    data.create_issue
-        .and_then(|m| m.issue)
-        .map(|i| i.id)
-        .ok_or_else(|| format_err!("issue creation failed"))
+    Ok(data
+        .search
+        .nodes
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|node| match node {
+            search_issues::SearchIssuesSearchNodes::Issue(issue) => Some(issue),
+            _ => None,
+        })
+        .map(|issue| FoundIssue {
+            id: issue.id,
+            number: issue.number,
+            body: issue.body,
+        })
+        .collect())
 }
 
 #[derive(GraphQLQuery)]
@@ -383,8 +1123,20 @@ pub fn create_issue(
 )]
 struct RemoveLabels;
 
-pub fn remove_labels(token: &str, labelable: String, labels: Vec<String>) -> Result<(), Error> {
-    perform_query::<RemoveLabels>(token, remove_labels::Variables { labelable, labels })?;
+fn remove_labels(
+    endpoint: &str,
+    token: &str,
+    labelable: String,
+    labels: Vec<String>,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let variables = remove_labels::Variables { labelable, labels };
+
+    if dry_run {
+        return print_dry_run_mutation::<RemoveLabels>(variables);
+    }
+
+    perform_query::<RemoveLabels>(endpoint, token, variables)?;
 
     Ok(())
 }
@@ -397,8 +1149,14 @@ pub fn remove_labels(token: &str, labelable: String, labels: Vec<String>) -> Res
 )]
 struct CloseIssue;
 
-pub fn close_issue(token: &str, id: String) -> Result<(), Error> {
-    perform_query::<CloseIssue>(token, close_issue::Variables { id })?;
+fn close_issue(endpoint: &str, token: &str, id: String, dry_run: bool) -> Result<(), Error> {
+    let variables = close_issue::Variables { id };
+
+    if dry_run {
+        return print_dry_run_mutation::<CloseIssue>(variables);
+    }
+
+    perform_query::<CloseIssue>(endpoint, token, variables)?;
 
     Ok(())
 }
@@ -411,13 +1169,15 @@ pub fn close_issue(token: &str, id: String) -> Result<(), Error> {
 )]
 struct IssueTitleAndBody;
 
-pub fn issue_title_and_body(
+fn issue_title_and_body(
+    endpoint: &str,
     token: &str,
     repo_owner: &str,
     repo_name: &str,
     number: i64,
 ) -> Result<(String, String), Error> {
     let data = perform_query::<IssueTitleAndBody>(
+        endpoint,
         token,
         issue_title_and_body::Variables {
             repo_owner: repo_owner.to_string(),
@@ -429,10 +1189,10 @@ pub fn issue_title_and_body(
     data.repository
         .and_then(|r| r.issue)
         .map(|issue| (issue.title, issue.body))
-        .ok_or_else(|| format_err!("issue not found"))
+        .ok_or_else(|| QueryError::NotFound(format!("issue {}", number)).into())
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Clone, Debug, Serialize)]
 struct FileBug<'a> {
     api_key: &'a str,
     product: String,
@@ -446,17 +1206,26 @@ struct FileBug<'a> {
 }
 
 #[derive(Debug, Deserialize)]
-struct FileBugResponse {
-    id: i64,
+#[serde(untagged)]
+enum FileBugResponse {
+    Success { id: i64 },
+    Error { code: Option<i64>, message: String },
 }
 
-pub fn file_bug(
+/// Bugzilla error codes for an API key that is invalid, or for a request that
+/// needs one but wasn't authenticated.
+const BUGZILLA_AUTH_ERROR_CODES: &[i64] = &[306, 410];
+
+#[allow(clippy::too_many_arguments)]
+fn file_bug(
+    bugzilla_url: &str,
     token: &str,
     product: String,
     component: String,
     summary: String,
     description: String,
     urls: Vec<String>,
+    dry_run: bool,
 ) -> Result<String, Error> {
     let query = FileBug {
         api_key: token,
@@ -470,21 +1239,56 @@ pub fn file_bug(
         see_also: urls,
     };
 
-    let response_string = CLIENT
-        .post(BUGZILLA_ENDPOINT)
-        .json(&query)
-        .send()
-        .context("could not perform network request")?
-        .text()
-        .context("could not read response")?;
+    let redacted = FileBug {
+        api_key: "[redacted]",
+        ..query.clone()
+    };
+
+    if dry_run {
+        println!(
+            "[dry run] FileBug {}",
+            serde_json::to_string_pretty(&redacted).context("could not serialize bug")?
+        );
+        return Ok(format!("{}/show_bug.cgi?id={}", bugzilla_url, DRY_RUN_ID));
+    }
 
-    let response: FileBugResponse = serde_json::from_str(&response_string)
-        .with_context(|_| format!("could not parse response ({})", response_string))?;
+    let url = format!("{}/rest/bug", bugzilla_url);
+    let recorded_request = serde_json::to_value(&redacted).context("could not serialize bug")?;
+    let (status, response_string) =
+        recording::exchange(&url, "FileBug", &recorded_request, || {
+            let start = Instant::now();
+            let mut response = CLIENT
+                .post(&url)
+                .json(&query)
+                .send()
+                .map_err(QueryError::Network)?;
+            log_request(&url, "FileBug", start, &response);
+            let text = response.text().map_err(QueryError::Body)?;
+            Ok((response.status(), text))
+        })?;
+
+    if status == StatusCode::UNAUTHORIZED {
+        return Err(QueryError::Unauthorized.into());
+    }
 
-    Ok(format!(
-        "https://bugzilla.mozilla.org/show_bug.cgi?id={}",
-        response.id
-    ))
+    match serde_json::from_str(&response_string) {
+        Ok(FileBugResponse::Success { id }) => {
+            logging::info("filed bug", &[("bug_id", id.into())]);
+            Ok(format!("{}/show_bug.cgi?id={}", bugzilla_url, id))
+        }
+        Ok(FileBugResponse::Error { code, .. })
+            if BUGZILLA_AUTH_ERROR_CODES.contains(&code.unwrap_or_default()) =>
+        {
+            Err(QueryError::Unauthorized.into())
+        }
+        Ok(FileBugResponse::Error { code, message }) => {
+            Err(QueryError::Bugzilla { code, message }.into())
+        }
+        Err(_) if !status.is_success() => Err(QueryError::HttpStatus(status.as_u16()).into()),
+        Err(e) => Err(e
+            .context(format!("could not parse response ({})", response_string))
+            .into()),
+    }
 }
 
 #[derive(GraphQLQuery)]
@@ -495,11 +1299,369 @@ pub fn file_bug(
 )]
 struct AddIssueComment;
 
-pub fn add_issue_comment(token: &str, issue_id: String, body: String) -> Result<(), Error> {
-    perform_query::<AddIssueComment>(token, add_issue_comment::Variables { id: issue_id, body })?;
+fn add_issue_comment(
+    endpoint: &str,
+    token: &str,
+    issue_id: String,
+    body: String,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let variables = add_issue_comment::Variables { id: issue_id, body };
+
+    if dry_run {
+        return print_dry_run_mutation::<AddIssueComment>(variables);
+    }
+
+    perform_query::<AddIssueComment>(endpoint, token, variables)?;
 
     Ok(())
 }
 
-const GITHUB_ENDPOINT: &'static str = "https://api.github.com/graphql";
-const BUGZILLA_ENDPOINT: &'static str = "https://bugzilla.mozilla.org/rest/bug";
+/// Fetches a repository's `config.toml` from the raw content server.
+fn repo_config(raw_content_url: &str, repo_owner: &str, repo_name: &str) -> Result<String, Error> {
+    let url = format!(
+        "{}/{}/{}/master/config.toml",
+        raw_content_url, repo_owner, repo_name
+    );
+    let repo = Value::from(format!("{}/{}", repo_owner, repo_name));
+    let (_, text) = recording::exchange(&url, "repo config", &repo, || {
+        let start = Instant::now();
+        let mut response = CLIENT
+            .get(&url)
+            .send()
+            .context("could not perform network request")?;
+        log_request(&url, "repo config", start, &response);
+        let text = response.text().context("could not read request body")?;
+        Ok((response.status(), text))
+    })?;
+    Ok(text)
+}
+
+/// The GitHub API, as configured in the config file.
+pub struct GitHub {
+    endpoint: String,
+    token: String,
+    raw_content_url: String,
+    page_sizes: PageSizes,
+    dry_run: bool,
+}
+
+impl GitHub {
+    pub fn new(config: &Config) -> GitHub {
+        GitHub {
+            endpoint: config.github_endpoint.clone(),
+            token: config.github_key.clone(),
+            raw_content_url: config.raw_content_url.clone(),
+            page_sizes: config.page_sizes,
+            dry_run: config.dry_run,
+        }
+    }
+}
+
+impl GitHubClient for GitHub {
+    fn repo_config(&self, repo_owner: &str, repo_name: &str) -> Result<String, Error> {
+        repo_config(&self.raw_content_url, repo_owner, repo_name)
+    }
+
+    fn updated_issues(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        since: &str,
+    ) -> Result<Vec<UpdatedIssue>, Error> {
+        updated_issues(
+            &self.endpoint,
+            &self.token,
+            repo_owner,
+            repo_name,
+            since,
+            self.page_sizes.issues,
+        )
+    }
+
+    fn issue(&self, repo_owner: &str, repo_name: &str, number: i64) -> Result<UpdatedIssue, Error> {
+        issue(&self.endpoint, &self.token, repo_owner, repo_name, number)
+    }
+
+    fn pull_request(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        number: i64,
+    ) -> Result<UpdatedIssue, Error> {
+        pull_request(&self.endpoint, &self.token, repo_owner, repo_name, number)
+    }
+
+    fn discussion(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        number: i64,
+    ) -> Result<UpdatedIssue, Error> {
+        discussion(&self.endpoint, &self.token, repo_owner, repo_name, number)
+    }
+
+    fn issue_comments(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        number: i64,
+    ) -> Result<Vec<IssueComment>, Error> {
+        issue_comments(
+            &self.endpoint,
+            &self.token,
+            repo_owner,
+            repo_name,
+            number,
+            self.page_sizes.comments,
+        )
+    }
+
+    fn updated_pull_requests(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        since: &str,
+    ) -> Result<Vec<UpdatedIssue>, Error> {
+        updated_pull_requests(
+            &self.endpoint,
+            &self.token,
+            repo_owner,
+            repo_name,
+            since,
+            self.page_sizes.issues,
+        )
+    }
+
+    fn pull_request_comments(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        number: i64,
+    ) -> Result<Vec<IssueComment>, Error> {
+        pull_request_comments(
+            &self.endpoint,
+            &self.token,
+            repo_owner,
+            repo_name,
+            number,
+            self.page_sizes.comments,
+        )
+    }
+
+    fn updated_discussions(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        since: &str,
+    ) -> Result<Vec<UpdatedIssue>, Error> {
+        updated_discussions(
+            &self.endpoint,
+            &self.token,
+            repo_owner,
+            repo_name,
+            since,
+            self.page_sizes.issues,
+        )
+    }
+
+    fn discussion_comments(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        number: i64,
+    ) -> Result<Vec<IssueComment>, Error> {
+        discussion_comments(
+            &self.endpoint,
+            &self.token,
+            repo_owner,
+            repo_name,
+            number,
+            self.page_sizes.comments,
+        )
+    }
+
+    fn issue_title_and_body(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        number: i64,
+    ) -> Result<(String, String), Error> {
+        issue_title_and_body(&self.endpoint, &self.token, repo_owner, repo_name, number)
+    }
+
+    fn known_labels(&self, repo_owner: &str, repo_name: &str) -> Result<Vec<KnownLabel>, Error> {
+        known_labels(
+            &self.endpoint,
+            &self.token,
+            repo_owner,
+            repo_name,
+            self.page_sizes.labels,
+        )
+    }
+
+    fn repo_id(&self, repo_owner: &str, repo_name: &str) -> Result<Option<String>, Error> {
+        repo_id(&self.endpoint, &self.token, repo_owner, repo_name)
+    }
+
+    fn search_issues(
+        &self,
+        repo_owner: &str,
+        repo_name: &str,
+        text: &str,
+    ) -> Result<Vec<FoundIssue>, Error> {
+        search_issues(&self.endpoint, &self.token, repo_owner, repo_name, text)
+    }
+
+    fn create_label(&mut self, repo_id: &str, name: &str, color: &str) -> Result<String, Error> {
+        create_label(
+            &self.endpoint,
+            &self.token,
+            repo_id,
+            name,
+            color,
+            self.dry_run,
+        )
+    }
+
+    fn create_issue(
+        &mut self,
+        repo_id: &str,
+        title: String,
+        body: Option<String>,
+        labels: Option<Vec<String>>,
+    ) -> Result<CreatedIssue, Error> {
+        create_issue(
+            &self.endpoint,
+            &self.token,
+            repo_id,
+            title,
+            body,
+            labels,
+            self.dry_run,
+        )
+    }
+
+    fn remove_labels(&mut self, labelable: String, labels: Vec<String>) -> Result<(), Error> {
+        remove_labels(&self.endpoint, &self.token, labelable, labels, self.dry_run)
+    }
+
+    fn close_issue(&mut self, id: String) -> Result<(), Error> {
+        close_issue(&self.endpoint, &self.token, id, self.dry_run)
+    }
+
+    fn add_issue_comment(&mut self, issue_id: String, body: String) -> Result<(), Error> {
+        add_issue_comment(&self.endpoint, &self.token, issue_id, body, self.dry_run)
+    }
+}
+
+/// The Bugzilla REST API, as configured in the config file.
+pub struct Bugzilla {
+    url: String,
+    api_key: String,
+    dry_run: bool,
+}
+
+impl Bugzilla {
+    pub fn new(config: &Config) -> Bugzilla {
+        Bugzilla {
+            url: config.bugzilla_url.clone(),
+            api_key: config.bugzilla_key.clone(),
+            dry_run: config.dry_run,
+        }
+    }
+}
+
+impl BugTracker for Bugzilla {
+    fn file_bug(
+        &mut self,
+        product: String,
+        component: String,
+        summary: String,
+        description: String,
+        urls: Vec<String>,
+    ) -> Result<String, Error> {
+        file_bug(
+            &self.url,
+            &self.api_key,
+            product,
+            component,
+            summary,
+            description,
+            urls,
+            self.dry_run,
+        )
+    }
+}
+
+/// Placeholder ID returned from mutations in dry-run mode, so that any
+/// follow-up tasks can still be planned.
+const DRY_RUN_ID: &str = "DRY_RUN";
+
+thread_local! {
+    /// Kept per thread, since it is only used by the thread that makes the
+    /// requests, and so that tests running in parallel don't see each
+    /// other's rate limits.
+    static RATE_LIMIT: Cell<Option<RateLimit>> = const { Cell::new(None) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comment(id: i64, created_at: &str, last_edited_at: Option<&str>) -> IssueComment {
+        IssueComment {
+            url: format!(
+                "https://github.com/w3c/csswg-drafts/issues/1#issuecomment-{}",
+                id
+            ),
+            created_at: created_at.to_string(),
+            last_edited_at: last_edited_at.map(String::from),
+            body_text: String::new(),
+        }
+    }
+
+    #[test]
+    fn all_comments_fetches_every_page() {
+        // Only the comment on the oldest page was changed recently, by an
+        // edit.
+        let mut pages = vec![
+            (
+                None,
+                vec![comment(
+                    1,
+                    "2018-12-01T00:00:00Z",
+                    Some("2019-03-01T00:00:00Z"),
+                )],
+            ),
+            (
+                Some(String::from("page-1")),
+                vec![comment(2, "2018-12-02T00:00:00Z", None)],
+            ),
+            (
+                Some(String::from("page-2")),
+                vec![comment(3, "2018-12-03T00:00:00Z", None)],
+            ),
+        ];
+        let mut requested = Vec::new();
+        let comments = all_comments(|before| {
+            requested.push(before);
+            Ok(pages.pop().unwrap())
+        })
+        .unwrap();
+
+        assert_eq!(
+            requested,
+            vec![
+                None,
+                Some(String::from("page-2")),
+                Some(String::from("page-1"))
+            ]
+        );
+        let ids = comments
+            .iter()
+            .map(|c| c.url.rsplit('-').next().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec!["1", "2", "3"]);
+    }
+}
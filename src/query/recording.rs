@@ -0,0 +1,181 @@
+//! Recording the GitHub and Bugzilla requests made during a run, so that the
+//! run can be reproduced elsewhere by replaying their responses.
+//!
+//! Each request and its response are written to a numbered JSON file in the
+//! recording directory.  Only the GraphQL variables or Bugzilla request body
+//! are kept, not the request headers, so the GitHub token is never recorded,
+//! and the Bugzilla API key is redacted from the request body before it gets
+//! here.  Requests that fail without a response are not recorded.
+//!
+//! When replaying, each request is answered with the first unused recorded
+//! response for the same operation and request, regardless of the endpoint,
+//! so that a recording can be replayed with a different config file.
+//!
+//! The state the run started from is saved in the recording directory too,
+//! as `state`.  A replayed run starts from that state rather than the one in
+//! the state directory, so that it makes the same requests, and never saves
+//! its state.
+//!
+//! Recording and replaying apply to the thread that started them, which for
+//! wg-tracker is the one that makes all the requests.
+
+use failure::{format_err, Error, ResultExt};
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+/// The name of the file in a recording directory that holds the state the
+/// run started from.
+const STATE_FILE: &str = "state";
+
+#[derive(Deserialize, Serialize)]
+struct Exchange {
+    url: String,
+    operation: String,
+    request: Value,
+    status: u16,
+    response: String,
+}
+
+enum Mode {
+    Record {
+        directory: PathBuf,
+        count: usize,
+    },
+    Replay {
+        directory: PathBuf,
+        /// The recorded exchanges, oldest first, with those that have been
+        /// replayed taken out.
+        exchanges: Vec<Option<Exchange>>,
+    },
+}
+
+/// Starts recording requests to the given directory, which must be empty
+/// or not exist.
+pub fn record(directory: &str) -> Result<(), Error> {
+    fs::create_dir_all(directory).context("could not create recording directory")?;
+    if fs::read_dir(directory)
+        .context("could not read recording directory")?
+        .next()
+        .is_some()
+    {
+        return Err(format_err!(
+            "recording directory {} is not empty",
+            directory
+        ));
+    }
+    set_mode(Mode::Record {
+        directory: PathBuf::from(directory),
+        count: 0,
+    });
+    Ok(())
+}
+
+/// Starts answering requests from the recording in the given directory.
+pub fn replay(directory: &str) -> Result<(), Error> {
+    let mut paths = fs::read_dir(directory)
+        .context("could not read recording directory")?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Result<Vec<_>, Error>>()
+        .context("could not read recording directory")?;
+    paths.retain(|p| p.extension() == Some(OsStr::new("json")));
+    paths.sort();
+
+    let exchanges = paths
+        .iter()
+        .map(|path| read_exchange(path).map(Some))
+        .collect::<Result<Vec<_>, Error>>()?;
+    set_mode(Mode::Replay {
+        directory: PathBuf::from(directory),
+        exchanges,
+    });
+    Ok(())
+}
+
+fn set_mode(mode: Mode) {
+    MODE.with(|m| *m.borrow_mut() = Some(mode));
+}
+
+/// Returns where to save the state the run starts from, if we are
+/// recording.
+pub fn recorded_state_path() -> Option<PathBuf> {
+    MODE.with(|mode| match &*mode.borrow() {
+        Some(Mode::Record { directory, .. }) => Some(directory.join(STATE_FILE)),
+        _ => None,
+    })
+}
+
+/// Returns the path of the state to start the run from, if we are
+/// replaying.
+pub fn replayed_state_path() -> Option<PathBuf> {
+    MODE.with(|mode| match &*mode.borrow() {
+        Some(Mode::Replay { directory, .. }) => Some(directory.join(STATE_FILE)),
+        _ => None,
+    })
+}
+
+/// Returns the number of recorded exchanges that haven't been replayed.
+#[cfg(test)]
+pub fn unreplayed() -> usize {
+    MODE.with(|mode| match &*mode.borrow() {
+        Some(Mode::Replay { exchanges, .. }) => exchanges.iter().filter(|e| e.is_some()).count(),
+        _ => 0,
+    })
+}
+
+fn read_exchange(path: &Path) -> Result<Exchange, Error> {
+    let file = File::open(path)
+        .with_context(|_| format!("could not open recorded request {}", path.display()))?;
+    Ok(serde_json::from_reader(file)
+        .with_context(|_| format!("could not parse recorded request {}", path.display()))?)
+}
+
+/// Performs a request with `send`, which returns the response status and
+/// body, recording the request and response if we are recording.  If we are
+/// replaying, the recorded response is returned instead.
+pub fn exchange<F>(
+    url: &str,
+    operation: &str,
+    request: &Value,
+    send: F,
+) -> Result<(StatusCode, String), Error>
+where
+    F: FnOnce() -> Result<(StatusCode, String), Error>,
+{
+    MODE.with(|mode| match &mut *mode.borrow_mut() {
+        None => send(),
+        Some(Mode::Record { directory, count }) => {
+            let (status, response) = send()?;
+            *count += 1;
+            let path = directory.join(format!("{:05}-{}.json", count, operation.replace(' ', "-")));
+            let exchange = Exchange {
+                url: url.to_string(),
+                operation: operation.to_string(),
+                request: request.clone(),
+                status: status.as_u16(),
+                response,
+            };
+            let file = File::create(&path).context("could not create recorded request")?;
+            serde_json::to_writer_pretty(file, &exchange)
+                .context("could not write recorded request")?;
+            Ok((status, exchange.response))
+        }
+        Some(Mode::Replay { exchanges, .. }) => {
+            let exchange = exchanges
+                .iter_mut()
+                .find(|e| matches!(e, Some(e) if e.operation == operation && e.request == *request))
+                .and_then(Option::take)
+                .ok_or_else(|| format_err!("no recorded response for {} {}", operation, request))?;
+            let status = StatusCode::from_u16(exchange.status)
+                .context("recorded response has an invalid status")?;
+            Ok((status, exchange.response))
+        }
+    })
+}
+
+thread_local! {
+    static MODE: RefCell<Option<Mode>> = const { RefCell::new(None) };
+}
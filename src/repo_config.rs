@@ -1,6 +1,9 @@
 use failure::{Error, ResultExt};
 use std::collections::HashMap;
 
+/// The repo config of each decisions repository, keyed by "owner/name".
+pub type RepoConfigs = HashMap<String, RepoConfig>;
+
 #[derive(Debug, Default, Deserialize)]
 pub struct RepoConfig {
     pub labels: Option<RepoConfigLabels>,
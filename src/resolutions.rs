@@ -0,0 +1,240 @@
+use failure::{format_err, Error, ResultExt};
+use regex::Regex;
+
+/// The `[resolutions]` table of the config file.
+#[derive(Debug, Default, Deserialize)]
+pub struct ResolutionsConfig {
+    /// Regular expressions matching a line that records a resolution.  The
+    /// first capture group of each is the text of the resolution.  Defaults
+    /// to only matching lines that begin with "RESOLVED: ".
+    pub patterns: Option<Vec<String>>,
+    /// Whether indented lines following a resolution are treated as a
+    /// continuation of it.
+    #[serde(default)]
+    pub continuation_lines: bool,
+}
+
+/// Finds the resolutions recorded in a WG comment.
+#[derive(Debug)]
+pub struct ResolutionExtractor {
+    patterns: Vec<Regex>,
+    continuation_lines: bool,
+}
+
+impl Default for ResolutionExtractor {
+    fn default() -> ResolutionExtractor {
+        ResolutionExtractor {
+            patterns: vec![Regex::new(DEFAULT_PATTERN).unwrap()],
+            continuation_lines: false,
+        }
+    }
+}
+
+impl ResolutionExtractor {
+    pub fn new(config: &ResolutionsConfig) -> Result<ResolutionExtractor, Error> {
+        let patterns = match &config.patterns {
+            Some(patterns) => patterns
+                .iter()
+                .map(|p| {
+                    let regex = Regex::new(p)
+                        .with_context(|_| format!("invalid resolution pattern '{}'", p))?;
+                    if regex.captures_len() < 2 {
+                        return Err(format_err!(
+                            "resolution pattern '{}' has no capture group",
+                            p
+                        ));
+                    }
+                    Ok(regex)
+                })
+                .collect::<Result<Vec<_>, Error>>()?,
+            None => vec![Regex::new(DEFAULT_PATTERN).unwrap()],
+        };
+
+        Ok(ResolutionExtractor {
+            patterns,
+            continuation_lines: config.continuation_lines,
+        })
+    }
+
+    pub fn extract(&self, text: &str) -> Vec<String> {
+        let mut resolutions: Vec<String> = Vec::new();
+        let mut continuing = false;
+
+        for line in text.lines() {
+            if let Some(resolution) = self.match_line(line) {
+                resolutions.push(resolution);
+                continuing = self.continuation_lines;
+            } else if continuing && line.starts_with(char::is_whitespace) && !line.trim().is_empty()
+            {
+                let resolution = resolutions.last_mut().unwrap();
+                resolution.push(' ');
+                resolution.push_str(line.trim());
+            } else {
+                continuing = false;
+            }
+        }
+
+        resolutions
+    }
+
+    fn match_line(&self, line: &str) -> Option<String> {
+        self.patterns
+            .iter()
+            .filter_map(|p| p.captures(line))
+            .filter_map(|cs| cs.get(1))
+            .map(|m| m.as_str().trim().to_string())
+            .find(|r| !r.is_empty())
+    }
+}
+
+const DEFAULT_PATTERN: &str = r"^RESOLVED: (.*)$";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Patterns that handle the variations commonly seen in minutes.
+    fn lenient() -> ResolutionExtractor {
+        ResolutionExtractor::new(&ResolutionsConfig {
+            patterns: Some(vec![String::from(
+                r"^\s*(?:[*-]\s+)?[*_]*(?i:resolved|resolution)[*_]*:[*_]*\s*(.*?)[*_]*\s*$",
+            )]),
+            continuation_lines: true,
+        })
+        .unwrap()
+    }
+
+    // Comment bodies are matched in GitHub's `bodyText` form, which has the
+    // markdown already stripped: no bold, list bullets or code spans.
+
+    const BOT_MINUTES: &str = "\
+The CSS Working Group just discussed [css-grid] Clarify min-content contribution, and agreed to the following:
+
+RESOLVED: Use the min-content size of the item when it spans flexible tracks
+RESOLVED: Publish a new WD of css-grid-2
+
+
+The full IRC log of that discussion
+<fantasai> Topic: [css-grid] Clarify min-content contribution
+<fantasai> github: https://github.com/w3c/csswg-drafts/issues/1234
+<TabAtkins> Seems reasonable to me.
+<astearns> Any objections?
+<fantasai> RESOLVED: Use the min-content size of the item when it spans flexible tracks
+";
+
+    /// A summary written by hand, with the resolution in bold.
+    const HAND_SUMMARY: &str = "\
+Discussed at the Cupertino F2F.
+RESOLVED: Close no change, the current behavior is web-compatible.
+@tabatkins will add a note to the spec explaining why.
+";
+
+    /// A summary that uses \"Resolution:\" rather than \"RESOLVED:\".
+    const RESOLUTION_SUMMARY: &str = "\
+Summary of the call:
+RESOLUTION: Rename the property to text-wrap-style
+Resolution: defer text-wrap: pretty details to level 5
+";
+
+    #[test]
+    fn default_matches_bot_minutes() {
+        assert_eq!(
+            ResolutionExtractor::default().extract(BOT_MINUTES),
+            vec![
+                "Use the min-content size of the item when it spans flexible tracks",
+                "Publish a new WD of css-grid-2",
+            ]
+        );
+    }
+
+    #[test]
+    fn default_matches_hand_summary() {
+        assert_eq!(
+            ResolutionExtractor::default().extract(HAND_SUMMARY),
+            vec!["Close no change, the current behavior is web-compatible."]
+        );
+    }
+
+    #[test]
+    fn default_ignores_variants() {
+        let default = ResolutionExtractor::default();
+        assert!(default.extract(RESOLUTION_SUMMARY).is_empty());
+        assert!(default
+            .extract("**RESOLVED:** foo\n  RESOLVED: bar\nResolved: baz\n__Resolved__: qux")
+            .is_empty());
+    }
+
+    #[test]
+    fn lenient_matches_resolution() {
+        assert_eq!(
+            lenient().extract(RESOLUTION_SUMMARY),
+            vec![
+                "Rename the property to text-wrap-style",
+                "defer text-wrap: pretty details to level 5",
+            ]
+        );
+    }
+
+    #[test]
+    fn lenient_matches_variants() {
+        let text = "\
+**RESOLVED:** Close no change
+  RESOLVED: Rename the property to `text-wrap-style`
+* RESOLVED: Accept the edits
+- Resolved: defer to level 5
+__Resolved__: tests are required
+";
+        assert_eq!(
+            lenient().extract(text),
+            vec![
+                "Close no change",
+                "Rename the property to `text-wrap-style`",
+                "Accept the edits",
+                "defer to level 5",
+                "tests are required",
+            ]
+        );
+    }
+
+    #[test]
+    fn lenient_ignores_irc_log() {
+        assert_eq!(lenient().extract(BOT_MINUTES).len(), 2);
+    }
+
+    #[test]
+    fn continuation_lines() {
+        let text = "\
+RESOLVED: Make the anchor() function resolve against the
+    containing block when there is no valid anchor element
+<astearns> next topic
+RESOLVED: Publish FPWD
+
+  This line is not part of the resolution.
+";
+        assert_eq!(
+            lenient().extract(text),
+            vec![
+                "Make the anchor() function resolve against the containing block when there is \
+                 no valid anchor element",
+                "Publish FPWD",
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_resolutions_ignored() {
+        assert!(ResolutionExtractor::default()
+            .extract("RESOLVED: \nRESOLVED:")
+            .is_empty());
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        let config = |p: &str| ResolutionsConfig {
+            patterns: Some(vec![String::from(p)]),
+            continuation_lines: false,
+        };
+        assert!(ResolutionExtractor::new(&config("RESOLVED: (")).is_err());
+        assert!(ResolutionExtractor::new(&config("RESOLVED: .*")).is_err());
+    }
+}
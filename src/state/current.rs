@@ -1,7 +1,13 @@
-use crate::config::Config;
-use crate::query;
-use crate::repo_config::RepoConfig;
-use crate::util::{escape_markdown, extract_urls};
+use super::migrations::{self, STATE_VERSION};
+use crate::client::{BugTracker, GitHubClient};
+use crate::config::{split_repo_id, Config};
+use crate::logging;
+use crate::metrics;
+use crate::query::{self, IssueKind, QueryError};
+use crate::repo_config::{RepoConfig, RepoConfigs};
+use crate::util::{error_message, escape_markdown, extract_urls, print_info};
+use crate::webhook::WebhookEvent;
+use chrono::{Duration, Utc};
 use failure::{format_err, Error, ResultExt};
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt;
@@ -9,57 +15,208 @@ use std::fs::{self, File};
 use std::io::Write;
 use std::mem;
 use std::path::Path;
+use std::slice;
+use std::time::Instant;
+
+/// The longest a failed task waits before it is retried, however many times it
+/// has failed.
+const MAX_RETRY_DELAY_SECONDS: i64 = 24 * 60 * 60;
 
 #[derive(Default, Deserialize, Serialize)]
 pub struct State {
-    tasks: VecDeque<Box<dyn Task>>,
+    pub(super) tasks: VecDeque<QueuedTask>,
     posted_tasks: Vec<Box<dyn Task>>,
-    handled_wg_comments: HashSet<String>,
-    handled_decisions_issues: HashSet<i64>,
+    #[serde(default)]
+    pub(super) dead_tasks: Vec<QueuedTask>,
+    /// Per-repository state for each WG repository, keyed by "owner/name".
+    pub(super) wg_repos: HashMap<String, WgRepoState>,
+    /// Per-repository state for each decisions repository, keyed by
+    /// "owner/name".
+    pub(super) decisions_repos: HashMap<String, DecisionsRepoState>,
+    /// The handled comments, tracking issues and handled decisions issues
+    /// that have been added, changed or removed since the state was last
+    /// saved, so that stores which save incrementally don't have to write
+    /// them all.
+    #[serde(skip)]
+    pub(super) changes: Vec<Change>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub(super) struct WgRepoState {
+    pub(super) last_time: String,
+    /// The same as `last_time`, for pull requests and discussions, which are
+    /// queried separately from issues.
+    pub(super) last_pull_request_time: String,
+    pub(super) last_discussion_time: String,
+    /// The URLs of the WG comments we have handled, and when we last handled
+    /// each of them.
+    pub(super) handled_comments: HashMap<String, String>,
+    /// The resolutions we have tracked for each handled WG comment, so that
+    /// we can notice when a comment is edited to add more.
+    pub(super) handled_resolutions: HashMap<String, Vec<String>>,
+    /// The most recent decisions issue we filed for each WG issue, so that
+    /// further resolutions can be added to it.
+    pub(super) tracking_issues: HashMap<i64, TrackingIssue>,
+    /// The same as `tracking_issues`, for pull requests and discussions.
+    pub(super) pull_request_tracking_issues: HashMap<i64, TrackingIssue>,
+    pub(super) discussion_tracking_issues: HashMap<i64, TrackingIssue>,
+}
+
+impl WgRepoState {
+    /// Returns the time up to which we have seen updates to the items of the
+    /// given kind.
+    fn cursor(&self, kind: IssueKind) -> &str {
+        match kind {
+            IssueKind::Issue => &self.last_time,
+            IssueKind::PullRequest => &self.last_pull_request_time,
+            IssueKind::Discussion => &self.last_discussion_time,
+        }
+    }
+
+    fn cursor_mut(&mut self, kind: IssueKind) -> &mut String {
+        match kind {
+            IssueKind::Issue => &mut self.last_time,
+            IssueKind::PullRequest => &mut self.last_pull_request_time,
+            IssueKind::Discussion => &mut self.last_discussion_time,
+        }
+    }
+
+    /// Returns the tracking issues for the items of the given kind, keyed by
+    /// their number.
+    pub(super) fn tracking_issues(&self, kind: IssueKind) -> &HashMap<i64, TrackingIssue> {
+        match kind {
+            IssueKind::Issue => &self.tracking_issues,
+            IssueKind::PullRequest => &self.pull_request_tracking_issues,
+            IssueKind::Discussion => &self.discussion_tracking_issues,
+        }
+    }
+
+    pub(super) fn tracking_issues_mut(
+        &mut self,
+        kind: IssueKind,
+    ) -> &mut HashMap<i64, TrackingIssue> {
+        match kind {
+            IssueKind::Issue => &mut self.tracking_issues,
+            IssueKind::PullRequest => &mut self.pull_request_tracking_issues,
+            IssueKind::Discussion => &mut self.discussion_tracking_issues,
+        }
+    }
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub(super) struct DecisionsRepoState {
+    pub(super) last_time: String,
+    /// The decisions issues we have filed bugs for, and when we did so.
+    pub(super) handled_issues: HashMap<i64, String>,
     #[serde(skip)]
     known_labels: Option<HashMap<String, String>>,
     #[serde(skip)]
-    decisions_repo_id: Option<String>,
-    last_time_wg: String,
-    last_time_decisions: String,
+    repo_id: Option<String>,
+}
+
+/// The number of handled comments and decisions issues that were pruned, and
+/// the number that are left.
+#[derive(Default)]
+pub struct GcStats {
+    pub comments_pruned: usize,
+    pub comments_kept: usize,
+    pub issues_pruned: usize,
+    pub issues_kept: usize,
+}
+
+/// A change to part of the state that grows without bound.
+pub(super) enum Change {
+    /// A WG comment (by repository and URL) was handled, or more of its
+    /// resolutions were, or it was pruned.
+    HandledComment(String, String),
+    /// The tracking issue for a WG item (by repository, kind and number) was
+    /// set.
+    TrackingIssue(String, IssueKind, i64),
+    /// A decisions issue (by repository and number) was handled, or was
+    /// pruned.
+    HandledDecisionsIssue(String, i64),
 }
 
 impl State {
-    pub fn new(date: &str) -> State {
+    pub fn new() -> State {
         State {
             tasks: VecDeque::new(),
             posted_tasks: Vec::new(),
-            handled_wg_comments: HashSet::new(),
-            handled_decisions_issues: HashSet::new(),
-            known_labels: None,
-            decisions_repo_id: None,
-            last_time_wg: format!("{}T00:00:00Z", date),
-            last_time_decisions: String::from("2019-01-01T00:00:00Z"),
+            dead_tasks: Vec::new(),
+            wg_repos: HashMap::new(),
+            decisions_repos: HashMap::new(),
+            changes: Vec::new(),
         }
     }
 
-    pub fn from_versioned_str(version: u32, json: &str) -> Result<State, Error> {
-        if version != 1 {
-            return Err(format_err!("unknown state file version number {}", version));
-        }
-        Ok(serde_json::from_str(json)
+    /// Parses a state file of the given version, upgrading it to the current
+    /// version first if necessary.
+    pub fn from_versioned_str(version: u32, json: &str, config: &Config) -> Result<State, Error> {
+        let mut value: serde_json::Value = serde_json::from_str(json)
+            .context(format!("could not parse state file v{}", version))?;
+        migrations::migrate(&mut value, version, config)?;
+        Ok(serde_json::from_value(value)
             .context(format!("could not parse state file v{}", version))?)
     }
 
-    pub fn check_for_updates(&mut self) {
-        self.tasks.push_back(Box::new(QueryWGIssuesTask {
-            since: self.last_time_wg.clone(),
-        }));
-        self.tasks.push_back(Box::new(QueryDecisionsIssuesTask {
-            since: self.last_time_decisions.clone(),
-        }));
+    /// Queues tasks to look for updates in each repository, other than those
+    /// for which a query is already queued, or dead.
+    pub fn check_for_updates(&mut self, config: &Config) {
+        let queued = self
+            .tasks
+            .iter()
+            .chain(&self.dead_tasks)
+            .filter_map(QueuedTask::chain)
+            .collect::<HashSet<_>>();
+        let mut new_tasks: Vec<Box<dyn Task>> = Vec::new();
+        for wg_repo in &config.wg_repos {
+            let id = wg_repo.id();
+            let repo_state = self.wg_repos.entry(id.clone()).or_insert_with(|| {
+                let start_time = format!("{}T00:00:00Z", config.start_date);
+                WgRepoState {
+                    last_time: start_time.clone(),
+                    last_pull_request_time: start_time.clone(),
+                    last_discussion_time: start_time,
+                    ..Default::default()
+                }
+            });
+            for &kind in IssueKind::ALL {
+                new_tasks.push(Box::new(QueryWGIssuesTask {
+                    wg_repo: id.clone(),
+                    kind,
+                    since: repo_state.cursor(kind).to_string(),
+                }));
+            }
+        }
+        for id in config.decisions_repos() {
+            let repo_state =
+                self.decisions_repos
+                    .entry(id.clone())
+                    .or_insert_with(|| DecisionsRepoState {
+                        last_time: format!("{}T00:00:00Z", config.start_date),
+                        ..Default::default()
+                    });
+            let since = repo_state.last_time.clone();
+            new_tasks.push(Box::new(QueryDecisionsIssuesTask {
+                decisions_repo: id,
+                since,
+            }));
+        }
+        for task in new_tasks {
+            if let Some(chain) = task.chain() {
+                if queued.contains(&chain) {
+                    continue;
+                }
+            }
+            self.tasks.push_back(QueuedTask::new(task, None));
+        }
     }
 
     pub fn save(&self, path: &Path, temp_path: &Path) -> Result<(), Error> {
         {
             let mut file =
                 File::create(temp_path).context("could not create temporary state file")?;
-            writeln!(file, "1").context("could not write temporary state file")?;
+            writeln!(file, "{}", STATE_VERSION).context("could not write temporary state file")?;
             serde_json::to_writer_pretty(&mut file, self)
                 .context("could not write temporary state file")?;
         }
@@ -67,30 +224,410 @@ impl State {
         Ok(())
     }
 
-    pub fn iterate(&mut self, config: &Config, repo_config: &RepoConfig) -> Result<(), Error> {
-        if !self.posted_tasks.is_empty() {
-            let mut new_tasks = Vec::new();
-            mem::swap(&mut new_tasks, &mut self.posted_tasks);
-            new_tasks.extend(self.tasks.drain(..));
-            self.tasks.extend(new_tasks.drain(..));
+    /// Runs the next task that is ready, against the given GitHub and bug
+    /// tracker.
+    pub fn iterate(
+        &mut self,
+        config: &Config,
+        repo_configs: &RepoConfigs,
+        github: &mut dyn GitHubClient,
+        bug_tracker: &mut dyn BugTracker,
+    ) -> Result<(), Error> {
+        self.merge_posted_tasks();
+
+        let index = match self.next_task_index(&now()) {
+            Some(index) => index,
+            None => return Ok(()),
+        };
+
+        let mut task = self.tasks.remove(index).unwrap();
+        let chain = task.chain();
+
+        let log_fields = task_log_fields(&*task.task);
+        let task_type = log_fields[0].1.as_str().unwrap_or_default().to_string();
+        let _span = logging::span(log_fields);
+        logging::info("task started", &[]);
+        let start = Instant::now();
+        let result = task
+            .task
+            .run(self, config, repo_configs, github, bug_tracker);
+
+        let e = match result {
+            Ok(()) => {
+                logging::info(
+                    "task finished",
+                    &[
+                        ("outcome", "ok".into()),
+                        ("duration_ms", logging::elapsed_ms(start)),
+                    ],
+                );
+                self.merge_posted_tasks_from(index, chain.as_deref());
+                return Ok(());
+            }
+            Err(e) => e,
+        };
+        metrics::inc_labeled(metrics::TASK_FAILURES, "task", &task_type);
+        let log_failure = |outcome: &str| {
+            let log = if outcome == "retry" {
+                logging::warn
+            } else {
+                logging::error
+            };
+            log(
+                "task failed",
+                &[
+                    ("outcome", outcome.into()),
+                    ("error", error_message(&e).into()),
+                    ("duration_ms", logging::elapsed_ms(start)),
+                ],
+            );
+        };
+
+        {
+            let query_error = e.downcast_ref::<QueryError>();
+            if matches!(query_error, Some(e) if e.is_fatal()) {
+                // Leave the task untouched, since the problem is not with it.
+                // The caller will stop the run.
+                log_failure("fatal");
+                self.tasks.insert(index, task);
+                self.merge_posted_tasks_from(index + 1, chain.as_deref());
+                return Err(e);
+            }
+
+            // Leave failed tasks where they were in the queue, but don't try
+            // them again until their backoff delay has passed.  Tasks that
+            // keep failing, or which failed in a way that retrying won't
+            // fix, are moved to the dead task list, so that they don't block
+            // the rest of the queue forever.  Either way, the rest of the
+            // task's chain waits for it.
+            task.attempts += 1;
+            task.last_error = Some(error_message(&e));
+            if task.attempts >= config.max_task_attempts
+                || matches!(query_error, Some(e) if e.is_permanent())
+            {
+                log_failure("dead");
+                task.next_attempt = None;
+                self.dead_tasks.push(task);
+                self.merge_posted_tasks_from(index, chain.as_deref());
+            } else {
+                log_failure("retry");
+                let exponent = (task.attempts - 1).min(16);
+                let delay = config
+                    .retry_delay_seconds
+                    .saturating_mul(1 << exponent)
+                    .min(MAX_RETRY_DELAY_SECONDS);
+                let delay = Duration::seconds(delay);
+                task.next_attempt = Some(format_time(&(Utc::now() + delay)));
+                self.tasks.insert(index, task);
+                self.merge_posted_tasks_from(index + 1, chain.as_deref());
+            }
         }
 
-        if self.tasks.is_empty() {
-            return Ok(());
+        Err(e)
+    }
+
+    /// Returns whether there are no more tasks that can be run now.  Tasks
+    /// that are waiting to be retried later may still remain in the queue.
+    pub fn is_finished(&self) -> bool {
+        self.posted_tasks.is_empty() && self.next_task_index(&now()).is_none()
+    }
+
+    /// Returns the index of the first task in the queue that is ready to run,
+    /// skipping tasks whose chain is blocked by an earlier task that is
+    /// waiting to be retried, or by a dead task.
+    fn next_task_index(&self, now: &str) -> Option<usize> {
+        let mut blocked = self
+            .dead_tasks
+            .iter()
+            .filter_map(QueuedTask::chain)
+            .collect::<HashSet<_>>();
+        for (i, task) in self.tasks.iter().enumerate() {
+            let chain = task.chain();
+            if let Some(chain) = &chain {
+                if blocked.contains(chain) {
+                    continue;
+                }
+            }
+            if task.is_ready(now) {
+                return Some(i);
+            }
+            blocked.extend(chain);
         }
+        None
+    }
 
-        let task = self.tasks.pop_front().unwrap();
-        let result = task.run(self, config, repo_config);
+    pub fn print_status(&self) {
+        let now = now();
+        let mut wg_repos = self.wg_repos.iter().collect::<Vec<_>>();
+        wg_repos.sort_by_key(|(id, _)| *id);
+        for (id, repo_state) in wg_repos {
+            println!("{}:", id);
+            println!("  last_time: {}", repo_state.last_time);
+            println!(
+                "  last_pull_request_time: {}",
+                repo_state.last_pull_request_time
+            );
+            println!(
+                "  last_discussion_time: {}",
+                repo_state.last_discussion_time
+            );
+            println!("  handled comments: {}", repo_state.handled_comments.len());
+            println!(
+                "  tracking issues: {}",
+                IssueKind::ALL
+                    .iter()
+                    .map(|&kind| repo_state.tracking_issues(kind).len())
+                    .sum::<usize>()
+            );
+        }
+        let mut decisions_repos = self.decisions_repos.iter().collect::<Vec<_>>();
+        decisions_repos.sort_by_key(|(id, _)| *id);
+        for (id, repo_state) in decisions_repos {
+            println!("{}:", id);
+            println!("  last_time: {}", repo_state.last_time);
+            println!("  handled issues: {}", repo_state.handled_issues.len());
+        }
+        println!(
+            "queued tasks: {} ({} waiting to be retried)",
+            self.tasks.len() + self.posted_tasks.len(),
+            self.tasks.iter().filter(|t| !t.is_ready(&now)).count()
+        );
+        println!("dead tasks: {}", self.dead_tasks.len());
+    }
 
-        if result.is_err() {
-            self.tasks.push_front(task);
+    /// Forgets the comments and decisions issues that were handled more than
+    /// `handled_retention_days` ago and before their repository's cursors.
+    /// Queries only return them again if they have since been updated, and
+    /// by then the handled record is very unlikely to matter.
+    pub fn gc(&mut self, config: &Config) -> GcStats {
+        let horizon = format_time(&(Utc::now() - Duration::days(config.handled_retention_days)));
+        let mut stats = GcStats::default();
+
+        for (id, repo_state) in &mut self.wg_repos {
+            let cutoff = IssueKind::ALL
+                .iter()
+                .map(|&kind| repo_state.cursor(kind))
+                .fold(horizon.as_str(), |a, b| a.min(b))
+                .to_string();
+            let pruned = repo_state
+                .handled_comments
+                .iter()
+                .filter(|(_, handled_at)| **handled_at < cutoff)
+                .map(|(url, _)| url.clone())
+                .collect::<Vec<_>>();
+            for url in pruned {
+                repo_state.handled_comments.remove(&url);
+                repo_state.handled_resolutions.remove(&url);
+                self.changes.push(Change::HandledComment(id.clone(), url));
+                stats.comments_pruned += 1;
+            }
+            stats.comments_kept += repo_state.handled_comments.len();
         }
 
-        result
+        for (id, repo_state) in &mut self.decisions_repos {
+            let cutoff = horizon.as_str().min(&repo_state.last_time).to_string();
+            let pruned = repo_state
+                .handled_issues
+                .iter()
+                .filter(|(_, handled_at)| **handled_at < cutoff)
+                .map(|(number, _)| *number)
+                .collect::<Vec<_>>();
+            for number in pruned {
+                repo_state.handled_issues.remove(&number);
+                self.changes
+                    .push(Change::HandledDecisionsIssue(id.clone(), number));
+                stats.issues_pruned += 1;
+            }
+            stats.issues_kept += repo_state.handled_issues.len();
+        }
+
+        stats
     }
 
-    pub fn is_finished(&self) -> bool {
-        self.tasks.is_empty() && self.posted_tasks.is_empty()
+    /// Updates the gauges that describe the task queue.
+    pub fn record_metrics(&self) {
+        let now = now();
+        metrics::set(
+            metrics::QUEUE_DEPTH,
+            (self.tasks.len() + self.posted_tasks.len()) as f64,
+        );
+        metrics::set(
+            metrics::QUEUE_WAITING,
+            self.tasks.iter().filter(|t| !t.is_ready(&now)).count() as f64,
+        );
+        metrics::set(metrics::DEAD_TASKS, self.dead_tasks.len() as f64);
+    }
+
+    pub fn print_queue(&mut self) {
+        self.merge_posted_tasks();
+        println!("queued tasks:");
+        for (i, task) in self.tasks.iter().enumerate() {
+            println!("{}: {}", i, task);
+        }
+        println!("dead tasks:");
+        for (i, task) in self.dead_tasks.iter().enumerate() {
+            println!("{}: {}", i, task);
+        }
+    }
+
+    /// Removes a task from the queue, or from the dead task list if `dead` is
+    /// true, using the indexes shown by `print_queue`.
+    pub fn drop_task(&mut self, index: usize, dead: bool) -> Result<QueuedTask, Error> {
+        self.merge_posted_tasks();
+        let task = if dead {
+            if index < self.dead_tasks.len() {
+                Some(self.dead_tasks.remove(index))
+            } else {
+                None
+            }
+        } else {
+            self.tasks.remove(index)
+        };
+        task.ok_or_else(|| format_err!("no task with index {}", index))
+    }
+
+    /// Moves all dead tasks back onto the queue, and makes any tasks waiting
+    /// to be retried ready to run immediately.  Dead tasks go back in front of
+    /// the rest of their chain, or onto the end of the queue otherwise.
+    pub fn retry_tasks(&mut self) {
+        self.merge_posted_tasks();
+        for task in &mut self.tasks {
+            task.next_attempt = None;
+        }
+        for task in mem::take(&mut self.dead_tasks) {
+            let chain = task.chain();
+            let task = QueuedTask::new(task.task, task.chain);
+            let index = chain.and_then(|chain| {
+                self.tasks
+                    .iter()
+                    .position(|t| t.chain().as_ref() == Some(&chain))
+            });
+            match index {
+                Some(index) => self.tasks.insert(index, task),
+                None => self.tasks.push_back(task),
+            }
+        }
+    }
+
+    /// Queues up a task to look at all the comments in a WG issue, pull
+    /// request or discussion again, regardless of when they were posted.
+    pub fn reprocess_wg_issue(&mut self, wg_repo: &str, kind: IssueKind, number: i64) {
+        self.tasks.push_back(QueuedTask::new(
+            Box::new(QueryWGIssueTask {
+                wg_repo: wg_repo.to_string(),
+                kind,
+                number,
+            }),
+            None,
+        ));
+    }
+
+    fn wg_repo_state(&mut self, wg_repo: &str) -> &mut WgRepoState {
+        self.wg_repos.entry(wg_repo.to_string()).or_default()
+    }
+
+    /// Queues tasks to handle a webhook delivery, if it is for one of the
+    /// repositories we track.
+    pub fn handle_webhook_event(&mut self, config: &Config, event: WebhookEvent) {
+        match event {
+            WebhookEvent::IssueComment {
+                repo,
+                kind,
+                issue_number,
+                issue_title,
+                issue_labels,
+                created_at,
+            } => {
+                if config.wg_repo(&repo).is_ok() {
+                    self.post_task(QueryWGIssueCommentsTask {
+                        wg_repo: repo,
+                        kind,
+                        number: issue_number,
+                        issue_title,
+                        issue_labels,
+                        since: created_at,
+                    });
+                }
+            }
+            WebhookEvent::Issue {
+                repo,
+                issue_number,
+                issue_id,
+                issue_labels,
+            } => {
+                if config.decisions_repos().contains(&repo) {
+                    self.post_task(ProcessDecisionsIssueTask {
+                        decisions_repo: repo,
+                        issue_number,
+                        issue_id,
+                        issue_labels,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Posts tasks to look up the labels and ID of a decisions repository
+    /// if we don't know them yet, returning whether we already do.
+    fn query_decisions_repo_details(&mut self, decisions_repo: &str) -> bool {
+        let repo_state = self.decisions_repo_state(decisions_repo);
+        let (has_labels, has_id) = (
+            repo_state.known_labels.is_some(),
+            repo_state.repo_id.is_some(),
+        );
+        if !has_labels {
+            self.post_task(QueryDecisionsKnownLabelsTask {
+                decisions_repo: decisions_repo.to_string(),
+            });
+        }
+        if !has_id {
+            self.post_task(QueryDecisionsRepoID {
+                decisions_repo: decisions_repo.to_string(),
+            });
+        }
+        has_labels && has_id
+    }
+
+    fn decisions_repo_state(&mut self, decisions_repo: &str) -> &mut DecisionsRepoState {
+        self.decisions_repos
+            .entry(decisions_repo.to_string())
+            .or_default()
+    }
+
+    /// Moves newly posted tasks to the front of the queue, or behind the
+    /// last queued task of their chain if there is one.
+    pub(super) fn merge_posted_tasks(&mut self) {
+        self.merge_posted_tasks_from(0, None);
+    }
+
+    /// Moves tasks posted by a task in `chain` into the queue.  Tasks that
+    /// continue that chain go at `index`, where the task that posted them
+    /// was; other tasks are merged as in `merge_posted_tasks`.
+    fn merge_posted_tasks_from(&mut self, mut index: usize, chain: Option<&str>) {
+        let mut front = 0;
+        for task in mem::take(&mut self.posted_tasks) {
+            let task = QueuedTask::new(task, chain.map(str::to_string));
+            let task_chain = task.chain();
+            let position = if task_chain.is_some() && task_chain.as_deref() == chain {
+                index
+            } else {
+                match task_chain.and_then(|c| {
+                    self.tasks
+                        .iter()
+                        .rposition(|t| t.chain().as_ref() == Some(&c))
+                }) {
+                    Some(last) => last + 1,
+                    None => {
+                        front += 1;
+                        front - 1
+                    }
+                }
+            };
+            if position <= index {
+                index += 1;
+            }
+            self.tasks.insert(position, task);
+        }
     }
 
     fn post_task<T: Task + 'static>(&mut self, task: T) {
@@ -98,18 +635,150 @@ impl State {
     }
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(super) struct TrackingIssue {
+    pub(super) decisions_repo: String,
+    pub(super) id: String,
+    pub(super) number: i64,
+}
+
+/// A task in the queue, along with information about previous failed attempts
+/// to run it.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct QueuedTask {
+    task: Box<dyn Task>,
+    /// The chain of the task that posted this one, for tasks that don't start
+    /// a chain of their own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    chain: Option<String>,
+    #[serde(default)]
+    attempts: u32,
+    #[serde(default)]
+    next_attempt: Option<String>,
+    #[serde(default)]
+    last_error: Option<String>,
+}
+
+impl QueuedTask {
+    fn new(task: Box<dyn Task>, chain: Option<String>) -> QueuedTask {
+        QueuedTask {
+            task,
+            chain,
+            attempts: 0,
+            next_attempt: None,
+            last_error: None,
+        }
+    }
+
+    /// Returns the chain this task belongs to.  A task doesn't run while an
+    /// earlier task in its chain is waiting to be retried or is dead.
+    fn chain(&self) -> Option<String> {
+        self.task.chain().or_else(|| self.chain.clone())
+    }
+
+    fn is_ready(&self, now: &str) -> bool {
+        match &self.next_attempt {
+            Some(t) => t.as_str() <= now,
+            None => true,
+        }
+    }
+}
+
+impl fmt::Display for QueuedTask {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#?}", self.task)?;
+        if self.attempts > 0 {
+            write!(f, "\n    attempts: {}", self.attempts)?;
+        }
+        if let Some(t) = &self.next_attempt {
+            write!(f, "\n    next attempt: {}", t)?;
+        }
+        if let Some(e) = &self.last_error {
+            write!(f, "\n    last error: {}", e)?;
+        }
+        Ok(())
+    }
+}
+
+/// Fields of a task that identify what it is working on.
+const TASK_LOG_FIELDS: &[&str] = &[
+    "wg_repo",
+    "decisions_repo",
+    "kind",
+    "number",
+    "issue_number",
+    "url",
+    "comment_url",
+    "issue_id",
+];
+
+/// Returns the type of a task and its identifying fields, for logging.
+fn task_log_fields(task: &dyn Task) -> Vec<(String, serde_json::Value)> {
+    let value = serde_json::to_value(task).unwrap_or_default();
+    let mut fields = vec![(String::from("task"), value["type"].clone())];
+    // The fields of the FileIssueTask inside an AddResolutionsTask are the
+    // interesting ones.
+    for object in &[&value, &value["file_issue"]] {
+        for key in TASK_LOG_FIELDS {
+            if let Some(v) = object.get(key) {
+                fields.push((key.to_string(), v.clone()));
+            }
+        }
+    }
+    fields
+}
+
+fn format_time(time: &chrono::DateTime<Utc>) -> String {
+    time.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+fn now() -> String {
+    format_time(&Utc::now())
+}
+
 #[typetag::serde(tag = "type")]
 trait Task: fmt::Debug {
     fn run(
         &self,
         state: &mut State,
         config: &Config,
-        repo_config: &RepoConfig,
+        repo_configs: &RepoConfigs,
+        github: &mut dyn GitHubClient,
+        bug_tracker: &mut dyn BugTracker,
     ) -> Result<(), Error>;
+
+    /// Returns the chain this task starts, if any.  Tasks it posts that
+    /// don't have a chain of their own continue it.
+    fn chain(&self) -> Option<String> {
+        None
+    }
+}
+
+/// Returns the chain for tasks working on a WG item, so that resolutions
+/// from one comment are filed before those from the next are looked at.
+fn wg_item_chain(wg_repo: &str, kind: IssueKind, number: i64) -> String {
+    format!("{} {:?} #{}", wg_repo, kind, number)
+}
+
+/// Returns the chain for tasks working on a decisions issue, so that it isn't
+/// closed until its bug has been filed.
+fn decisions_issue_chain(issue_id: &str) -> String {
+    format!("decisions issue {}", issue_id)
+}
+
+fn repo_config<'a>(
+    repo_configs: &'a RepoConfigs,
+    decisions_repo: &str,
+) -> Result<&'a RepoConfig, Error> {
+    repo_configs
+        .get(decisions_repo)
+        .ok_or_else(|| format_err!("no repo config for {}", decisions_repo))
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 struct QueryWGIssuesTask {
+    wg_repo: String,
+    kind: IssueKind,
     since: String,
 }
 
@@ -119,21 +788,27 @@ impl Task for QueryWGIssuesTask {
         &self,
         state: &mut State,
         config: &Config,
-        _repo_config: &RepoConfig,
+        _repo_configs: &RepoConfigs,
+        github: &mut dyn GitHubClient,
+        _bug_tracker: &mut dyn BugTracker,
     ) -> Result<(), Error> {
-        let issues = query::updated_issues(
-            &config.github_key,
-            &config.wg_repo_owner,
-            &config.wg_repo_name,
-            &self.since,
-        )?;
+        let wg_repo = config.wg_repo(&self.wg_repo)?;
+
+        let (owner, name) = (&wg_repo.owner, &wg_repo.name);
+        let issues = match self.kind {
+            IssueKind::Issue => github.updated_issues(owner, name, &self.since)?,
+            IssueKind::PullRequest => github.updated_pull_requests(owner, name, &self.since)?,
+            IssueKind::Discussion => github.updated_discussions(owner, name, &self.since)?,
+        };
 
         if let Some(issue) = issues.last() {
-            state.last_time_wg = issue.updated_at.clone();
+            *state.wg_repo_state(&self.wg_repo).cursor_mut(self.kind) = issue.updated_at.clone();
         }
 
         for issue in issues {
             state.post_task(QueryWGIssueCommentsTask {
+                wg_repo: self.wg_repo.clone(),
+                kind: self.kind,
                 number: issue.issue_number,
                 issue_title: issue.issue_title.clone(),
                 issue_labels: issue.issue_labels,
@@ -143,10 +818,55 @@ impl Task for QueryWGIssuesTask {
 
         Ok(())
     }
+
+    fn chain(&self) -> Option<String> {
+        Some(format!("query {} {:?}", self.wg_repo, self.kind))
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct QueryWGIssueTask {
+    wg_repo: String,
+    #[serde(default)]
+    kind: IssueKind,
+    number: i64,
+}
+
+#[typetag::serde]
+impl Task for QueryWGIssueTask {
+    fn run(
+        &self,
+        state: &mut State,
+        config: &Config,
+        _repo_configs: &RepoConfigs,
+        github: &mut dyn GitHubClient,
+        _bug_tracker: &mut dyn BugTracker,
+    ) -> Result<(), Error> {
+        let wg_repo = config.wg_repo(&self.wg_repo)?;
+
+        let (owner, name) = (&wg_repo.owner, &wg_repo.name);
+        let issue = match self.kind {
+            IssueKind::Issue => github.issue(owner, name, self.number)?,
+            IssueKind::PullRequest => github.pull_request(owner, name, self.number)?,
+            IssueKind::Discussion => github.discussion(owner, name, self.number)?,
+        };
+
+        state.post_task(QueryWGIssueCommentsTask {
+            wg_repo: self.wg_repo.clone(),
+            kind: self.kind,
+            number: issue.issue_number,
+            issue_title: issue.issue_title,
+            issue_labels: issue.issue_labels,
+            since: String::new(),
+        });
+
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 struct QueryDecisionsIssuesTask {
+    decisions_repo: String,
     since: String,
 }
 
@@ -164,80 +884,152 @@ impl Task for QueryDecisionsIssuesTask {
     fn run(
         &self,
         state: &mut State,
-        config: &Config,
-        repo_config: &RepoConfig,
+        _config: &Config,
+        repo_configs: &RepoConfigs,
+        github: &mut dyn GitHubClient,
+        _bug_tracker: &mut dyn BugTracker,
     ) -> Result<(), Error> {
-        let issues = query::updated_issues(
-            &config.github_key,
-            &config.decisions_repo_owner,
-            &config.decisions_repo_name,
-            &self.since,
-        )?;
+        let repo_config = repo_config(repo_configs, &self.decisions_repo)?;
+        let (owner, name) = split_repo_id(&self.decisions_repo);
+
+        let issues = github.updated_issues(owner, name, &self.since)?;
+
+        let repo_state = state.decisions_repo_state(&self.decisions_repo);
 
         if let Some(issue) = issues.last() {
-            state.last_time_decisions = issue.updated_at.clone();
+            repo_state.last_time = issue.updated_at.clone();
         }
 
         for issue in issues {
-            if state.handled_decisions_issues.contains(&issue.issue_number) {
-                continue;
-            }
+            handle_decisions_issue(
+                state,
+                &self.decisions_repo,
+                repo_config,
+                issue.issue_number,
+                issue.id,
+                issue.issue_labels,
+            )?;
+        }
 
-            if !issue.issue_labels.iter().any(|label| label.name == "bug") {
-                continue;
-            }
+        Ok(())
+    }
 
-            state.handled_decisions_issues.insert(issue.issue_number);
+    fn chain(&self) -> Option<String> {
+        Some(format!("query {}", self.decisions_repo))
+    }
+}
 
-            let mut components = Vec::new();
-            for label in issue.issue_labels {
-                if !label.name.starts_with("[spec] ") {
-                    continue;
-                }
+/// Files a bug for a decisions issue that has been given the "bug" label, if
+/// we haven't already.
+fn handle_decisions_issue(
+    state: &mut State,
+    decisions_repo: &str,
+    repo_config: &RepoConfig,
+    issue_number: i64,
+    issue_id: String,
+    issue_labels: Vec<query::IssueLabel>,
+) -> Result<(), Error> {
+    let repo_state = state.decisions_repo_state(decisions_repo);
 
-                let mut spec = String::from(&label.name["[spec] ".len()..]);
-                while let Some(c) = spec.pop() {
-                    if !(c >= '0' && c <= '9' || c == '-') {
-                        spec.push(c);
-                        break;
-                    }
-                }
+    if repo_state.handled_issues.contains_key(&issue_number) {
+        return Ok(());
+    }
 
-                if let Some(cs) = &repo_config.components {
-                    if let Some(c) = cs.get(&spec) {
-                        components.push(c);
-                    }
-                }
-            }
+    if !issue_labels.iter().any(|label| label.name == "bug") {
+        return Ok(());
+    }
 
-            let product_component = if components.len() == 1 {
-                Some(parse_component(&components[0]))
-            } else if let Some(cs) = &repo_config.components {
-                cs.get("default").map(|c| parse_component(&*c))
-            } else {
-                None
-            };
-            let product_component = product_component.unwrap_or(Ok(("Invalid Bugs", "General")))?;
+    repo_state.handled_issues.insert(issue_number, now());
+    state.changes.push(Change::HandledDecisionsIssue(
+        decisions_repo.to_string(),
+        issue_number,
+    ));
 
-            state.post_task(FileBugForDecisionsIssueTask {
-                product: product_component.0.to_string(),
-                component: product_component.1.to_string(),
-                issue_number: issue.issue_number,
-                issue_id: issue.id.clone(),
-            });
+    let mut components = Vec::new();
+    for label in issue_labels {
+        if !label.name.starts_with("[spec] ") {
+            continue;
+        }
 
-            state.post_task(RemoveDecisionsIssueBugLabelTask {
-                issue_id: issue.id.clone(),
-            });
-            state.post_task(CloseIssueTask { issue_id: issue.id });
+        let mut spec = String::from(&label.name["[spec] ".len()..]);
+        while let Some(c) = spec.pop() {
+            if !(c >= '0' && c <= '9' || c == '-') {
+                spec.push(c);
+                break;
+            }
         }
 
-        Ok(())
+        if let Some(cs) = &repo_config.components {
+            if let Some(c) = cs.get(&spec) {
+                components.push(c);
+            }
+        }
+    }
+
+    let product_component = if components.len() == 1 {
+        Some(parse_component(&components[0]))
+    } else if let Some(cs) = &repo_config.components {
+        cs.get("default").map(|c| parse_component(&*c))
+    } else {
+        None
+    };
+    let product_component = product_component.unwrap_or(Ok(("Invalid Bugs", "General")))?;
+
+    state.post_task(FileBugForDecisionsIssueTask {
+        decisions_repo: decisions_repo.to_string(),
+        product: product_component.0.to_string(),
+        component: product_component.1.to_string(),
+        issue_number,
+        issue_id: issue_id.clone(),
+    });
+
+    state.post_task(RemoveDecisionsIssueBugLabelTask {
+        decisions_repo: decisions_repo.to_string(),
+        issue_id: issue_id.clone(),
+    });
+    state.post_task(CloseIssueTask { issue_id });
+
+    Ok(())
+}
+
+/// Handles a decisions issue that a webhook told us about.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct ProcessDecisionsIssueTask {
+    decisions_repo: String,
+    issue_number: i64,
+    issue_id: String,
+    issue_labels: Vec<query::IssueLabel>,
+}
+
+#[typetag::serde]
+impl Task for ProcessDecisionsIssueTask {
+    fn run(
+        &self,
+        state: &mut State,
+        _config: &Config,
+        repo_configs: &RepoConfigs,
+        _github: &mut dyn GitHubClient,
+        _bug_tracker: &mut dyn BugTracker,
+    ) -> Result<(), Error> {
+        handle_decisions_issue(
+            state,
+            &self.decisions_repo,
+            repo_config(repo_configs, &self.decisions_repo)?,
+            self.issue_number,
+            self.issue_id.clone(),
+            self.issue_labels.clone(),
+        )
+    }
+
+    fn chain(&self) -> Option<String> {
+        Some(decisions_issue_chain(&self.issue_id))
     }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 struct QueryWGIssueCommentsTask {
+    wg_repo: String,
+    kind: IssueKind,
     number: i64,
     issue_title: String,
     issue_labels: Vec<query::IssueLabel>,
@@ -250,18 +1042,30 @@ impl Task for QueryWGIssueCommentsTask {
         &self,
         state: &mut State,
         config: &Config,
-        _repo_config: &RepoConfig,
+        _repo_configs: &RepoConfigs,
+        github: &mut dyn GitHubClient,
+        _bug_tracker: &mut dyn BugTracker,
     ) -> Result<(), Error> {
-        let comments = query::issue_comments(
-            &config.github_key,
-            &config.wg_repo_owner,
-            &config.wg_repo_name,
-            self.number,
-        )?;
+        let wg_repo = config.wg_repo(&self.wg_repo)?;
+
+        let (owner, name) = (&wg_repo.owner, &wg_repo.name);
+        let comments = match self.kind {
+            IssueKind::Issue => github.issue_comments(owner, name, self.number)?,
+            IssueKind::PullRequest => github.pull_request_comments(owner, name, self.number)?,
+            IssueKind::Discussion => github.discussion_comments(owner, name, self.number)?,
+        };
 
         for comment in comments {
-            if comment.created_at >= self.since {
+            // Look at edited comments too, since resolutions are often added
+            // to minutes after they are first posted.
+            let edited_since = match &comment.last_edited_at {
+                Some(t) => *t >= self.since,
+                None => false,
+            };
+            if comment.created_at >= self.since || edited_since {
                 state.post_task(ProcessWGCommentTask {
+                    wg_repo: self.wg_repo.clone(),
+                    kind: self.kind,
                     issue_number: self.number,
                     issue_title: self.issue_title.clone(),
                     issue_labels: self.issue_labels.clone(),
@@ -273,10 +1077,16 @@ impl Task for QueryWGIssueCommentsTask {
 
         Ok(())
     }
+
+    fn chain(&self) -> Option<String> {
+        Some(wg_item_chain(&self.wg_repo, self.kind, self.number))
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 struct ProcessWGCommentTask {
+    wg_repo: String,
+    kind: IssueKind,
     issue_number: i64,
     issue_title: String,
     issue_labels: Vec<query::IssueLabel>,
@@ -289,27 +1099,84 @@ impl Task for ProcessWGCommentTask {
     fn run(
         &self,
         state: &mut State,
-        _config: &Config,
-        repo_config: &RepoConfig,
+        config: &Config,
+        repo_configs: &RepoConfigs,
+        _github: &mut dyn GitHubClient,
+        _bug_tracker: &mut dyn BugTracker,
     ) -> Result<(), Error> {
-        const PREFIX: &'static str = "RESOLVED: ";
-
-        let resolutions = self
-            .body_text
-            .lines()
-            .filter(|line| line.starts_with(PREFIX))
-            .map(|line| line[PREFIX.len()..].to_string())
-            .collect::<Vec<_>>();
+        let resolutions = config
+            .wg_repo(&self.wg_repo)?
+            .resolution_extractor
+            .extract(&self.body_text);
 
         if resolutions.is_empty() {
+            logging::info("no resolutions in comment", &[]);
             return Ok(());
         }
 
-        if state.handled_wg_comments.contains(&self.url) {
+        let label_names = self
+            .issue_labels
+            .iter()
+            .map(|l| l.name.as_str())
+            .collect::<Vec<_>>();
+        let decisions_repo = config.route(&self.wg_repo, &label_names, &self.issue_title);
+        let repo_config = repo_config(repo_configs, &decisions_repo)?;
+
+        let repo_state = state.wg_repo_state(&self.wg_repo);
+        let handled_resolutions = repo_state.handled_resolutions.get(&self.url);
+
+        if handled_resolutions.is_none() && repo_state.handled_comments.contains_key(&self.url) {
+            // We handled this comment before we started recording which
+            // resolutions we saw, so we don't know which of its current
+            // resolutions were added by later edits.  Assume we've tracked
+            // them all, but say so, since any that were added won't be.
+            logging::warn(
+                "seeding resolutions for comment handled before they were recorded",
+                &[("resolutions", resolutions.clone().into())],
+            );
+            repo_state
+                .handled_resolutions
+                .insert(self.url.clone(), resolutions);
+            state.changes.push(Change::HandledComment(
+                self.wg_repo.clone(),
+                self.url.clone(),
+            ));
             return Ok(());
         }
 
-        state.handled_wg_comments.insert(self.url.clone());
+        let already_tracked = handled_resolutions.is_some()
+            || repo_state
+                .tracking_issues(self.kind)
+                .contains_key(&self.issue_number);
+        let new_resolutions = resolutions
+            .into_iter()
+            .filter(|r| !matches!(handled_resolutions, Some(rs) if rs.contains(r)))
+            .collect::<Vec<_>>();
+
+        if new_resolutions.is_empty() {
+            logging::info("resolutions already tracked", &[]);
+            return Ok(());
+        }
+
+        metrics::inc(metrics::RESOLUTIONS_DETECTED, new_resolutions.len());
+        logging::info(
+            "tracking resolutions",
+            &[
+                ("resolutions", new_resolutions.clone().into()),
+                ("routed_to", decisions_repo.clone().into()),
+            ],
+        );
+
+        repo_state.handled_comments.insert(self.url.clone(), now());
+        repo_state
+            .handled_resolutions
+            .entry(self.url.clone())
+            .or_default()
+            .extend(new_resolutions.iter().cloned());
+        state.changes.push(Change::HandledComment(
+            self.wg_repo.clone(),
+            self.url.clone(),
+        ));
 
         let mut desired_labels = Vec::new();
         if let Some(labels_config) = &repo_config.labels {
@@ -333,12 +1200,16 @@ impl Task for ProcessWGCommentTask {
 
         for label in &desired_labels {
             state.post_task(EnsureLabelTask {
+                decisions_repo: decisions_repo.clone(),
                 name: format!("[spec] {}", label.name),
                 color: label.color.clone(),
             });
         }
 
-        state.post_task(FileIssueTask {
+        let file_issue = FileIssueTask {
+            decisions_repo,
+            wg_repo: self.wg_repo.clone(),
+            kind: self.kind,
             issue_number: self.issue_number,
             issue_title: self.issue_title.clone(),
             issue_labels: desired_labels
@@ -346,31 +1217,135 @@ impl Task for ProcessWGCommentTask {
                 .map(|l| format!("[spec] {}", l.name))
                 .collect(),
             comment_url: self.url.clone(),
-            resolutions,
+            resolutions: new_resolutions,
+            previous_issue: None,
+        };
+
+        if already_tracked {
+            state.post_task(AddResolutionsTask { file_issue });
+        } else {
+            state.post_task(file_issue);
+        }
+
+        Ok(())
+    }
+
+    fn chain(&self) -> Option<String> {
+        Some(wg_item_chain(&self.wg_repo, self.kind, self.issue_number))
+    }
+}
+
+/// Adds further resolutions for a WG issue to its existing tracking issue,
+/// or files a new issue for them if that one has been closed.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct AddResolutionsTask {
+    file_issue: FileIssueTask,
+}
+
+#[typetag::serde]
+impl Task for AddResolutionsTask {
+    fn run(
+        &self,
+        state: &mut State,
+        config: &Config,
+        _repo_configs: &RepoConfigs,
+        github: &mut dyn GitHubClient,
+        _bug_tracker: &mut dyn BugTracker,
+    ) -> Result<(), Error> {
+        let file_issue = &self.file_issue;
+        let wg_repo = config.wg_repo(&file_issue.wg_repo)?;
+
+        let tracking_issue = match state
+            .wg_repo_state(&file_issue.wg_repo)
+            .tracking_issues(file_issue.kind)
+            .get(&file_issue.issue_number)
+        {
+            Some(tracking_issue) => Some(tracking_issue.clone()),
+            None => {
+                // We don't remember filing an issue for the WG issue, but if
+                // we are here because of an edited comment, we can find the
+                // issue we filed for the comment.
+                let (owner, name) = split_repo_id(&file_issue.decisions_repo);
+                github
+                    .search_issues(owner, name, &file_issue.comment_url)?
+                    .into_iter()
+                    .find(|issue| is_tracking_issue_for(&issue.body, &file_issue.comment_url, &[]))
+                    .map(|issue| TrackingIssue {
+                        decisions_repo: file_issue.decisions_repo.clone(),
+                        id: issue.id,
+                        number: issue.number,
+                    })
+            }
+        };
+
+        let tracking_issue = match tracking_issue {
+            Some(tracking_issue) => tracking_issue,
+            None => {
+                state.post_task(file_issue.clone());
+                return Ok(());
+            }
+        };
+
+        let (owner, name) = split_repo_id(&tracking_issue.decisions_repo);
+        let closed = github.issue(owner, name, tracking_issue.number)?.closed;
+
+        if closed {
+            // File the new issue alongside the old one, so that the link to
+            // it works.
+            state.post_task(FileIssueTask {
+                decisions_repo: tracking_issue.decisions_repo,
+                previous_issue: Some(tracking_issue.number),
+                ..file_issue.clone()
+            });
+            return Ok(());
+        }
+
+        let issue_url = file_issue.kind.url(&wg_repo.url(), file_issue.issue_number);
+        state.post_task(AddIssueCommentTask {
+            issue_id: tracking_issue.id,
+            body: format!(
+                "{} made for [{}/#{}]({}) in [this comment]({}):\n\
+                 \n\
+                 {}",
+                resolutions_were(&file_issue.resolutions),
+                wg_repo.name,
+                file_issue.issue_number,
+                issue_url,
+                file_issue.comment_url,
+                format_resolutions(&file_issue.resolutions),
+            ),
         });
 
         Ok(())
     }
+
+    fn chain(&self) -> Option<String> {
+        self.file_issue.chain()
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
-struct QueryDecisionsKnownLabelsTask;
+struct QueryDecisionsKnownLabelsTask {
+    decisions_repo: String,
+}
 
 #[typetag::serde]
 impl Task for QueryDecisionsKnownLabelsTask {
     fn run(
         &self,
         state: &mut State,
-        config: &Config,
-        _repo_config: &RepoConfig,
+        _config: &Config,
+        _repo_configs: &RepoConfigs,
+        github: &mut dyn GitHubClient,
+        _bug_tracker: &mut dyn BugTracker,
     ) -> Result<(), Error> {
-        let result = query::known_labels(
-            &config.github_key,
-            &config.decisions_repo_owner,
-            &config.decisions_repo_name,
-        )?;
+        let (owner, name) = split_repo_id(&self.decisions_repo);
+        let result = github.known_labels(owner, name)?;
 
-        let known_labels = state.known_labels.get_or_insert_with(|| HashMap::new());
+        let known_labels = state
+            .decisions_repo_state(&self.decisions_repo)
+            .known_labels
+            .get_or_insert_with(|| HashMap::new());
 
         for label in result {
             known_labels.insert(label.name, label.id);
@@ -382,6 +1357,7 @@ impl Task for QueryDecisionsKnownLabelsTask {
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 struct EnsureLabelTask {
+    decisions_repo: String,
     name: String,
     color: String,
 }
@@ -391,22 +1367,19 @@ impl Task for EnsureLabelTask {
     fn run(
         &self,
         state: &mut State,
-        config: &Config,
-        _repo_config: &RepoConfig,
+        _config: &Config,
+        _repo_configs: &RepoConfigs,
+        github: &mut dyn GitHubClient,
+        _bug_tracker: &mut dyn BugTracker,
     ) -> Result<(), Error> {
-        if state.known_labels.is_none() {
-            state.post_task(QueryDecisionsKnownLabelsTask);
+        if !state.query_decisions_repo_details(&self.decisions_repo) {
             state.post_task(self.clone());
             return Ok(());
         }
 
-        if state.decisions_repo_id.is_none() {
-            state.post_task(QueryDecisionsRepoID);
-            state.post_task(self.clone());
-            return Ok(());
-        }
+        let repo_state = state.decisions_repo_state(&self.decisions_repo);
 
-        if state
+        if repo_state
             .known_labels
             .as_ref()
             .unwrap()
@@ -415,14 +1388,13 @@ impl Task for EnsureLabelTask {
             return Ok(());
         }
 
-        let label_id = query::create_label(
-            &config.github_key,
-            state.decisions_repo_id.as_ref().unwrap(),
+        let label_id = github.create_label(
+            repo_state.repo_id.as_ref().unwrap(),
             &self.name,
             &self.color,
         )?;
 
-        state
+        repo_state
             .known_labels
             .as_mut()
             .unwrap()
@@ -433,39 +1405,82 @@ impl Task for EnsureLabelTask {
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
-struct QueryDecisionsRepoID;
+struct QueryDecisionsRepoID {
+    decisions_repo: String,
+}
 
 #[typetag::serde]
 impl Task for QueryDecisionsRepoID {
     fn run(
         &self,
         state: &mut State,
-        config: &Config,
-        _repo_config: &RepoConfig,
+        _config: &Config,
+        _repo_configs: &RepoConfigs,
+        github: &mut dyn GitHubClient,
+        _bug_tracker: &mut dyn BugTracker,
     ) -> Result<(), Error> {
-        let result = query::repo_id(
-            &config.github_key,
-            &config.decisions_repo_owner,
-            &config.decisions_repo_name,
-        )?;
+        let (owner, name) = split_repo_id(&self.decisions_repo);
+        let result = github.repo_id(owner, name)?;
 
         if result.is_none() {
             return Err(format_err!("repository not found"));
         }
 
-        state.decisions_repo_id = result;
+        state.decisions_repo_state(&self.decisions_repo).repo_id = result;
 
         Ok(())
     }
 }
 
+/// Returns an HTML comment to include in tracking issue bodies, so that we can
+/// find the tracking issue for a given WG comment later.
+fn tracking_marker(comment_url: &str) -> String {
+    format!("<!-- wg-tracker-comment: {} -->", comment_url)
+}
+
+/// Returns whether an issue body is that of a tracking issue for the given
+/// WG comment that lists all of the given resolutions.
+fn is_tracking_issue_for(body: &str, comment_url: &str, resolutions: &[String]) -> bool {
+    // Issues filed before we added the marker can still be recognized by
+    // their discussion link.
+    (body.contains(&tracking_marker(comment_url))
+        || body.contains(&format!("[Discussion.]({})", comment_url)))
+        && resolutions
+            .iter()
+            .all(|r| body.contains(&format_resolutions(slice::from_ref(r))))
+}
+
+fn resolutions_were(resolutions: &[String]) -> &'static str {
+    if resolutions.len() == 1 {
+        "A resolution was"
+    } else {
+        "Resolutions were"
+    }
+}
+
+fn format_resolutions(resolutions: &[String]) -> String {
+    resolutions
+        .iter()
+        .map(|s| format!("* RESOLVED: {}\n", escape_markdown(&s)))
+        .collect()
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 struct FileIssueTask {
+    decisions_repo: String,
+    wg_repo: String,
+    /// The kind of WG item the resolutions were made in, which determines
+    /// the URL we link to.
+    kind: IssueKind,
     issue_number: i64,
     issue_title: String,
     issue_labels: Vec<String>,
     comment_url: String,
     resolutions: Vec<String>,
+    /// The closed tracking issue that previous resolutions for the WG issue
+    /// were filed in, if any.
+    #[serde(default)]
+    previous_issue: Option<i64>,
 }
 
 #[typetag::serde]
@@ -474,26 +1489,17 @@ impl Task for FileIssueTask {
         &self,
         state: &mut State,
         config: &Config,
-        _repo_config: &RepoConfig,
+        _repo_configs: &RepoConfigs,
+        github: &mut dyn GitHubClient,
+        _bug_tracker: &mut dyn BugTracker,
     ) -> Result<(), Error> {
-        if state.known_labels.is_none() {
-            state.post_task(QueryDecisionsKnownLabelsTask);
-            state.post_task(self.clone());
-            return Ok(());
-        }
-
-        if state.decisions_repo_id.is_none() {
-            state.post_task(QueryDecisionsRepoID);
+        if !state.query_decisions_repo_details(&self.decisions_repo) {
             state.post_task(self.clone());
             return Ok(());
         }
 
-        let plural = if self.resolutions.len() == 1 {
-            "A resolution was"
-        } else {
-            "Resolutions were"
-        };
-        let issue_url = format!("{}/issues/{}", config.wg_repo_url(), self.issue_number);
+        let wg_repo = config.wg_repo(&self.wg_repo)?;
+        let issue_url = self.kind.url(&wg_repo.url(), self.issue_number);
         let body = format!(
             "{} made for [{}/#{}]({}).\n\
              \n\
@@ -501,47 +1507,130 @@ impl Task for FileIssueTask {
              \n\
              {}\n\
              \n\
-             [Discussion.]({})\n\
+             {}[Discussion.]({})\n\
              \n\
              ----\n\
              \n\
              To file a bug automatically for these resolutions, add the **bug** \
              label to the issue.\n\
              \n\
-             If no bug is needed, the issue can be closed.",
-            plural,
-            config.wg_repo_name,
+             If no bug is needed, the issue can be closed.\n\
+             \n\
+             {}",
+            resolutions_were(&self.resolutions),
+            wg_repo.name,
             self.issue_number,
             issue_url,
             escape_markdown(&self.issue_title),
-            self.resolutions
-                .iter()
-                .map(|s| format!("* RESOLVED: {}\n", escape_markdown(&s)))
-                .collect::<String>(),
+            format_resolutions(&self.resolutions),
+            self.previous_issue.map_or(String::new(), |number| format!(
+                "Earlier resolutions were tracked in [#{}]({}/issues/{}).\n\n",
+                number,
+                config.decisions_repo_url(&self.decisions_repo),
+                number
+            )),
             self.comment_url,
+            tracking_marker(&self.comment_url),
         );
 
+        // Check whether we already filed an issue for this comment, in case
+        // we lost track of it (e.g. because we died before saving state).
+        let (owner, name) = split_repo_id(&self.decisions_repo);
+        let existing = github
+            .search_issues(owner, name, &self.comment_url)?
+            .into_iter()
+            .filter(|issue| Some(issue.number) != self.previous_issue)
+            .find(|issue| is_tracking_issue_for(&issue.body, &self.comment_url, &self.resolutions));
+
+        if let Some(issue) = existing {
+            if github.issue(owner, name, issue.number)?.closed {
+                // As with AddResolutionsTask, file a new issue alongside the
+                // closed one rather than tracking the WG issue in it.
+                print_info(&format!(
+                    "found existing issue #{} for {}, but it is closed",
+                    issue.number, self.comment_url
+                ));
+                state.post_task(FileIssueTask {
+                    previous_issue: Some(issue.number),
+                    ..self.clone()
+                });
+                return Ok(());
+            }
+
+            print_info(&format!(
+                "found existing issue #{} for {}",
+                issue.number, self.comment_url
+            ));
+            state
+                .wg_repo_state(&self.wg_repo)
+                .tracking_issues_mut(self.kind)
+                .insert(
+                    self.issue_number,
+                    TrackingIssue {
+                        decisions_repo: self.decisions_repo.clone(),
+                        id: issue.id,
+                        number: issue.number,
+                    },
+                );
+            state.changes.push(Change::TrackingIssue(
+                self.wg_repo.clone(),
+                self.kind,
+                self.issue_number,
+            ));
+            return Ok(());
+        }
+
+        let repo_state = state.decisions_repo_state(&self.decisions_repo);
+
         let label_ids = self
             .issue_labels
             .iter()
-            .flat_map(|s| state.known_labels.as_ref().unwrap().get(s))
+            .flat_map(|s| repo_state.known_labels.as_ref().unwrap().get(s))
             .map(|s| s.to_string())
             .collect::<Vec<_>>();
 
-        query::create_issue(
-            &config.github_key,
-            state.decisions_repo_id.as_ref().unwrap(),
+        let issue = github.create_issue(
+            repo_state.repo_id.as_ref().unwrap(),
             self.issue_title.clone(),
             Some(body),
             Some(label_ids),
         )?;
+        metrics::inc(metrics::TRACKING_ISSUES_FILED, 1);
+
+        // In dry-run mode no issue was really created, so there is nothing
+        // that later resolutions could be added to.
+        if config.dry_run {
+            return Ok(());
+        }
+
+        state
+            .wg_repo_state(&self.wg_repo)
+            .tracking_issues_mut(self.kind)
+            .insert(
+                self.issue_number,
+                TrackingIssue {
+                    decisions_repo: self.decisions_repo.clone(),
+                    id: issue.id,
+                    number: issue.number,
+                },
+            );
+        state.changes.push(Change::TrackingIssue(
+            self.wg_repo.clone(),
+            self.kind,
+            self.issue_number,
+        ));
 
         Ok(())
     }
+
+    fn chain(&self) -> Option<String> {
+        Some(wg_item_chain(&self.wg_repo, self.kind, self.issue_number))
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 struct RemoveDecisionsIssueBugLabelTask {
+    decisions_repo: String,
     issue_id: String,
 }
 
@@ -550,16 +1639,22 @@ impl Task for RemoveDecisionsIssueBugLabelTask {
     fn run(
         &self,
         state: &mut State,
-        config: &Config,
-        _repo_config: &RepoConfig,
+        _config: &Config,
+        _repo_configs: &RepoConfigs,
+        github: &mut dyn GitHubClient,
+        _bug_tracker: &mut dyn BugTracker,
     ) -> Result<(), Error> {
-        if state.known_labels.is_none() {
-            state.post_task(QueryDecisionsKnownLabelsTask);
+        let repo_state = state.decisions_repo_state(&self.decisions_repo);
+
+        if repo_state.known_labels.is_none() {
+            state.post_task(QueryDecisionsKnownLabelsTask {
+                decisions_repo: self.decisions_repo.clone(),
+            });
             state.post_task(self.clone());
             return Ok(());
         }
 
-        let label_id = state
+        let label_id = repo_state
             .known_labels
             .as_ref()
             .unwrap()
@@ -567,10 +1662,14 @@ impl Task for RemoveDecisionsIssueBugLabelTask {
             .ok_or_else(|| format_err!("decisions repo missing 'bug' label"))?
             .clone();
 
-        query::remove_labels(&config.github_key, self.issue_id.clone(), vec![label_id])?;
+        github.remove_labels(self.issue_id.clone(), vec![label_id])?;
 
         Ok(())
     }
+
+    fn chain(&self) -> Option<String> {
+        Some(decisions_issue_chain(&self.issue_id))
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -583,17 +1682,25 @@ impl Task for CloseIssueTask {
     fn run(
         &self,
         _state: &mut State,
-        config: &Config,
-        _repo_config: &RepoConfig,
+        _config: &Config,
+        _repo_configs: &RepoConfigs,
+        github: &mut dyn GitHubClient,
+        _bug_tracker: &mut dyn BugTracker,
     ) -> Result<(), Error> {
-        query::close_issue(&config.github_key, self.issue_id.clone())?;
+        github.close_issue(self.issue_id.clone())?;
+        metrics::inc(metrics::DECISIONS_ISSUES_CLOSED, 1);
 
         Ok(())
     }
+
+    fn chain(&self) -> Option<String> {
+        Some(decisions_issue_chain(&self.issue_id))
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 struct FileBugForDecisionsIssueTask {
+    decisions_repo: String,
     product: String,
     component: String,
     issue_number: i64,
@@ -606,14 +1713,12 @@ impl Task for FileBugForDecisionsIssueTask {
         &self,
         state: &mut State,
         config: &Config,
-        _repo_config: &RepoConfig,
+        _repo_configs: &RepoConfigs,
+        github: &mut dyn GitHubClient,
+        _bug_tracker: &mut dyn BugTracker,
     ) -> Result<(), Error> {
-        let title_and_body = query::issue_title_and_body(
-            &config.github_key,
-            &config.decisions_repo_owner,
-            &config.decisions_repo_name,
-            self.issue_number,
-        )?;
+        let (owner, name) = split_repo_id(&self.decisions_repo);
+        let title_and_body = github.issue_title_and_body(owner, name, self.issue_number)?;
 
         let title = title_and_body.0;
         let body = title_and_body.1;
@@ -623,7 +1728,7 @@ impl Task for FileBugForDecisionsIssueTask {
 
         urls.push(format!(
             "{}/issues/{}",
-            config.decisions_repo_url(),
+            config.decisions_repo_url(&self.decisions_repo),
             self.issue_number
         ));
 
@@ -639,6 +1744,10 @@ impl Task for FileBugForDecisionsIssueTask {
 
         Ok(())
     }
+
+    fn chain(&self) -> Option<String> {
+        Some(decisions_issue_chain(&self.issue_id))
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -657,17 +1766,19 @@ impl Task for FileBugForDecisionsIssueWithDetailsTask {
     fn run(
         &self,
         state: &mut State,
-        config: &Config,
-        _repo_config: &RepoConfig,
+        _config: &Config,
+        _repo_configs: &RepoConfigs,
+        _github: &mut dyn GitHubClient,
+        bug_tracker: &mut dyn BugTracker,
     ) -> Result<(), Error> {
-        let url = query::file_bug(
-            &config.bugzilla_key,
+        let url = bug_tracker.file_bug(
             self.product.clone(),
             self.component.clone(),
             self.summary.clone(),
             self.description.clone(),
             self.urls.clone(),
         )?;
+        metrics::inc(metrics::BUGS_FILED, 1);
 
         state.post_task(AddIssueCommentTask {
             issue_id: self.issue_id.clone(),
@@ -676,6 +1787,10 @@ impl Task for FileBugForDecisionsIssueWithDetailsTask {
 
         Ok(())
     }
+
+    fn chain(&self) -> Option<String> {
+        Some(decisions_issue_chain(&self.issue_id))
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -689,11 +1804,443 @@ impl Task for AddIssueCommentTask {
     fn run(
         &self,
         _state: &mut State,
-        config: &Config,
-        _repo_config: &RepoConfig,
+        _config: &Config,
+        _repo_configs: &RepoConfigs,
+        github: &mut dyn GitHubClient,
+        _bug_tracker: &mut dyn BugTracker,
     ) -> Result<(), Error> {
-        query::add_issue_comment(&config.github_key, self.issue_id.clone(), self.body.clone())?;
+        github.add_issue_comment(self.issue_id.clone(), self.body.clone())?;
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{MemoryBugTracker, MemoryGitHub, MemoryIssue};
+
+    const CONFIG: &str = r#"
+        github_key = ""
+        bugzilla_key = ""
+        wg_repo_owner = "w3c"
+        wg_repo_name = "csswg-drafts"
+        decisions_repo_owner = "mozilla"
+        decisions_repo_name = "wg-decisions"
+        state_directory = ""
+        start_date = "2019-01-01"
+    "#;
+
+    const REPO_CONFIG: &str = r#"
+        [labels]
+        prefixes = ["css-"]
+
+        [components]
+        css-grid = "Core :: Layout: Grid"
+    "#;
+
+    const WG_REPO: &str = "w3c/csswg-drafts";
+    const DECISIONS_REPO: &str = "mozilla/wg-decisions";
+
+    struct Fixture {
+        config: Config,
+        repo_configs: RepoConfigs,
+        state: State,
+        github: MemoryGitHub,
+        bug_tracker: MemoryBugTracker,
+    }
+
+    impl Fixture {
+        fn new() -> Fixture {
+            let mut github = MemoryGitHub::new("2019-03-01T17:00:00Z");
+            github.add_repo(WG_REPO, "");
+            github.add_label(WG_REPO, "css-grid-2", "fbca04");
+            github.add_repo(DECISIONS_REPO, REPO_CONFIG);
+            github.add_label(DECISIONS_REPO, "bug", "d73a4a");
+
+            let mut repo_configs = RepoConfigs::new();
+            repo_configs.insert(
+                DECISIONS_REPO.to_string(),
+                RepoConfig::from_str(REPO_CONFIG).unwrap(),
+            );
+
+            Fixture {
+                config: Config::from_toml(CONFIG).unwrap(),
+                repo_configs,
+                state: State::new(),
+                github,
+                bug_tracker: Default::default(),
+            }
+        }
+
+        /// Queues tasks to look for updates, and runs tasks until there are
+        /// none left.
+        fn run(&mut self) {
+            self.state.check_for_updates(&self.config);
+            self.run_queue();
+        }
+
+        /// Runs tasks until there are none left.
+        fn run_queue(&mut self) {
+            while !self.state.is_finished() {
+                self.state
+                    .iterate(
+                        &self.config,
+                        &self.repo_configs,
+                        &mut self.github,
+                        &mut self.bug_tracker,
+                    )
+                    .unwrap();
+            }
+            assert!(self.state.tasks.is_empty(), "{:?}", self.state.tasks);
+            assert!(self.state.dead_tasks.is_empty());
+        }
+
+        fn decisions_issues(&self) -> &[MemoryIssue] {
+            &self.github.repo(DECISIONS_REPO).issues
+        }
+    }
+
+    #[test]
+    fn resolution_is_filed_in_decisions_repo() {
+        let mut f = Fixture::new();
+        let number = f.github.add_issue(
+            WG_REPO,
+            "[css-grid] Clarify min-content contribution",
+            "",
+            &["css-grid-2"],
+        );
+        f.github.add_comment(WG_REPO, number, "Any objections?");
+        let url = f
+            .github
+            .add_comment(WG_REPO, number, "RESOLVED: Use the min-content size");
+
+        f.run();
+
+        let issues = f.decisions_issues();
+        assert_eq!(issues.len(), 1);
+        let issue = &issues[0];
+        assert_eq!(issue.title, "[css-grid] Clarify min-content contribution");
+        assert!(issue
+            .body
+            .contains("* RESOLVED: Use the min\\-content size\n"));
+        assert!(issue.body.contains(&tracking_marker(&url)));
+        let repo = f.github.repo(DECISIONS_REPO);
+        let label = repo
+            .labels
+            .iter()
+            .find(|l| l.name == "[spec] css-grid-2")
+            .unwrap();
+        assert_eq!(label.color, "fbca04");
+        assert_eq!(issue.labels, vec![label.id.clone()]);
+
+        // Nothing new is filed when the comment is seen again.
+        f.github.now = String::from("2019-03-02T09:00:00Z");
+        f.github.add_comment(WG_REPO, number, "Thanks!");
+        f.run();
+        assert_eq!(f.decisions_issues().len(), 1);
+    }
+
+    #[test]
+    fn edited_comment_resolutions_are_added_to_tracking_issue() {
+        let mut f = Fixture::new();
+        let number = f.github.add_issue(WG_REPO, "Title", "", &[]);
+        let url = f.github.add_comment(WG_REPO, number, "RESOLVED: First");
+        f.run();
+
+        f.github.now = String::from("2019-03-02T09:00:00Z");
+        f.github
+            .edit_comment(WG_REPO, number, &url, "RESOLVED: First\nRESOLVED: Second");
+        f.run();
+
+        let issues = f.decisions_issues();
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].comments.len(), 1);
+        let comment = &issues[0].comments[0].body_text;
+        assert!(comment.contains("* RESOLVED: Second\n"));
+        assert!(!comment.contains("First"));
+    }
+
+    #[test]
+    fn resolutions_for_closed_tracking_issue_get_new_issue() {
+        let mut f = Fixture::new();
+        let number = f.github.add_issue(WG_REPO, "Title", "", &[]);
+        f.github.add_comment(WG_REPO, number, "RESOLVED: First");
+        f.run();
+
+        f.github.now = String::from("2019-03-02T09:00:00Z");
+        f.github.close(DECISIONS_REPO, 1);
+        f.github.add_comment(WG_REPO, number, "RESOLVED: Second");
+        f.run();
+
+        let issues = f.decisions_issues();
+        assert_eq!(issues.len(), 2);
+        assert!(issues[1].body.contains("* RESOLVED: Second\n"));
+        assert!(issues[1]
+            .body
+            .contains("Earlier resolutions were tracked in [#1]"));
+    }
+
+    #[test]
+    fn existing_issue_is_adopted_when_state_is_lost() {
+        let mut f = Fixture::new();
+        let number = f.github.add_issue(WG_REPO, "Title", "", &[]);
+        f.github.add_comment(WG_REPO, number, "RESOLVED: First");
+        f.run();
+
+        f.state = State::new();
+        f.run();
+
+        assert_eq!(f.decisions_issues().len(), 1);
+        let tracking_issue = &f.state.wg_repos[WG_REPO].tracking_issues[&number];
+        assert_eq!(tracking_issue.number, 1);
+    }
+
+    #[test]
+    fn closed_existing_issue_gets_new_issue_when_state_is_lost() {
+        let mut f = Fixture::new();
+        let number = f.github.add_issue(WG_REPO, "Title", "", &[]);
+        f.github.add_comment(WG_REPO, number, "RESOLVED: First");
+        f.run();
+
+        f.github.close(DECISIONS_REPO, 1);
+        f.state = State::new();
+        f.run();
+
+        let issues = f.decisions_issues();
+        assert_eq!(issues.len(), 2);
+        assert!(issues[1].body.contains("* RESOLVED: First\n"));
+        assert!(issues[1]
+            .body
+            .contains("Earlier resolutions were tracked in [#1]"));
+        let tracking_issue = &f.state.wg_repos[WG_REPO].tracking_issues[&number];
+        assert_eq!(tracking_issue.number, 2);
+
+        // The new issue is found, rather than the closed one, if state is
+        // lost again.
+        f.state = State::new();
+        f.run();
+        assert_eq!(f.decisions_issues().len(), 2);
+    }
+
+    #[test]
+    fn bug_label_files_bug_and_closes_issue() {
+        let mut f = Fixture::new();
+        f.github
+            .add_label(DECISIONS_REPO, "[spec] css-grid-2", "fbca04");
+        let number = f.github.add_issue(
+            DECISIONS_REPO,
+            "[css-grid] Clarify min-content contribution",
+            "A resolution was made.\n\n\
+             [Discussion.](https://github.com/w3c/csswg-drafts/issues/1#issuecomment-2)\n\n\
+             ----\n\n\
+             To file a bug automatically...",
+            &["bug", "[spec] css-grid-2"],
+        );
+
+        f.run();
+
+        assert_eq!(f.bug_tracker.bugs.len(), 1);
+        let bug = &f.bug_tracker.bugs[0];
+        assert_eq!(bug.product, "Core");
+        assert_eq!(bug.component, "Layout: Grid");
+        assert_eq!(bug.summary, "[css-grid] Clarify min-content contribution");
+        assert!(!bug.description.contains("To file a bug"));
+        assert_eq!(
+            bug.urls,
+            vec![
+                "https://github.com/w3c/csswg-drafts/issues/1#issuecomment-2",
+                "https://github.com/mozilla/wg-decisions/issues/1",
+            ]
+        );
+
+        let issue = f.github.repo(DECISIONS_REPO).issue(number);
+        assert!(issue.closed);
+        assert_eq!(issue.labels.len(), 1);
+        assert_eq!(issue.comments.len(), 1);
+        assert_eq!(issue.comments[0].body_text, MemoryBugTracker::bug_url(1));
+
+        // The issue is seen again since closing it updated it, but it has
+        // already been handled.
+        f.run();
+        assert_eq!(f.bug_tracker.bugs.len(), 1);
+    }
+
+    #[test]
+    fn decisions_issue_is_not_closed_until_bug_is_filed() {
+        let mut f = Fixture::new();
+        f.github
+            .add_label(DECISIONS_REPO, "[spec] css-grid-2", "fbca04");
+        let number = f.github.add_issue(
+            DECISIONS_REPO,
+            "[css-grid] Clarify min-content contribution",
+            "A resolution was made.",
+            &["bug", "[spec] css-grid-2"],
+        );
+        f.bug_tracker.unavailable = true;
+
+        f.state.check_for_updates(&f.config);
+        while !f.state.is_finished() {
+            let _ = f.state.iterate(
+                &f.config,
+                &f.repo_configs,
+                &mut f.github,
+                &mut f.bug_tracker,
+            );
+        }
+        let issue = f.github.repo(DECISIONS_REPO).issue(number);
+        assert!(!issue.closed);
+        assert_eq!(issue.labels.len(), 2);
+        assert_eq!(f.state.tasks.len(), 3, "{:?}", f.state.tasks);
+
+        f.bug_tracker.unavailable = false;
+        f.state.retry_tasks();
+        f.run();
+        assert_eq!(f.bug_tracker.bugs.len(), 1);
+        let issue = f.github.repo(DECISIONS_REPO).issue(number);
+        assert!(issue.closed);
+        assert_eq!(issue.labels.len(), 1);
+    }
+
+    #[test]
+    fn queries_are_not_queued_twice() {
+        let mut f = Fixture::new();
+        f.state.check_for_updates(&f.config);
+        let queued = f.state.tasks.len();
+        f.state.check_for_updates(&f.config);
+        assert_eq!(f.state.tasks.len(), queued);
+    }
+
+    #[test]
+    fn dry_run_does_not_record_tracking_issues() {
+        let mut f = Fixture::new();
+        f.config.dry_run = true;
+        let number = f.github.add_issue(WG_REPO, "Title", "", &[]);
+        f.github.add_comment(WG_REPO, number, "RESOLVED: First");
+        f.run();
+        assert!(f.state.wg_repos[WG_REPO].tracking_issues.is_empty());
+    }
+
+    #[test]
+    fn webhook_comments_are_routed_to_wg_repos() {
+        let mut f = Fixture::new();
+        let title = "[css-grid] Clarify min-content contribution";
+        let number = f.github.add_issue(WG_REPO, title, "", &["css-grid-2"]);
+        f.github
+            .add_comment(WG_REPO, number, "RESOLVED: Use the min-content size");
+        let event = |repo: &str| WebhookEvent::IssueComment {
+            repo: repo.to_string(),
+            kind: IssueKind::Issue,
+            issue_number: number,
+            issue_title: title.to_string(),
+            issue_labels: Vec::new(),
+            created_at: String::from("2019-03-01T17:00:00Z"),
+        };
+
+        // Comments in repositories other than the WG ones are ignored.
+        f.state
+            .handle_webhook_event(&f.config, event("w3c/fxtf-drafts"));
+        f.state
+            .handle_webhook_event(&f.config, event(DECISIONS_REPO));
+        assert!(f.state.is_finished());
+
+        f.state.handle_webhook_event(&f.config, event(WG_REPO));
+        f.run_queue();
+        assert_eq!(f.decisions_issues().len(), 1);
+    }
+
+    #[test]
+    fn webhook_issues_are_routed_to_decisions_repos() {
+        let mut f = Fixture::new();
+        let event = |repo: &str| WebhookEvent::Issue {
+            repo: repo.to_string(),
+            issue_number: 1,
+            issue_id: String::from("issue-1"),
+            issue_labels: Vec::new(),
+        };
+
+        f.state.handle_webhook_event(&f.config, event(WG_REPO));
+        assert!(f.state.is_finished());
+
+        f.state
+            .handle_webhook_event(&f.config, event(DECISIONS_REPO));
+        f.state.merge_posted_tasks();
+        assert_eq!(f.state.tasks.len(), 1);
+        let task = serde_json::to_value(&f.state.tasks[0]).unwrap();
+        assert_eq!(task["task"]["type"], "ProcessDecisionsIssueTask");
+    }
+
+    #[test]
+    fn gc_prunes_handled_items_behind_cursors_and_retention() {
+        let config = Config::from_toml(CONFIG).unwrap();
+        let days_ago = |days| format_time(&(Utc::now() - Duration::days(days)));
+        let comment = |id| {
+            format!(
+                "https://github.com/w3c/csswg-drafts/issues/1#issuecomment-{}",
+                id
+            )
+        };
+        let mut state = State::new();
+
+        // Comments handled after any of the cursors are kept, however old
+        // they are.
+        let repo_state = state.wg_repo_state(WG_REPO);
+        repo_state.last_time = String::from("2019-06-01T00:00:00Z");
+        repo_state.last_pull_request_time = String::from("2019-05-01T00:00:00Z");
+        repo_state.last_discussion_time = String::from("2019-07-01T00:00:00Z");
+        for &(id, handled_at) in &[
+            (1, "2019-04-01T00:00:00Z"),
+            (2, "2019-05-15T00:00:00Z"),
+            (3, "2019-06-15T00:00:00Z"),
+        ] {
+            repo_state
+                .handled_comments
+                .insert(comment(id), handled_at.to_string());
+            repo_state
+                .handled_resolutions
+                .insert(comment(id), vec![String::from("Resolution")]);
+        }
+
+        // Decisions issues are kept while they are within the retention
+        // period, even once the cursor has passed them.
+        let repo_state = state.decisions_repo_state(DECISIONS_REPO);
+        repo_state.last_time = days_ago(0);
+        repo_state.handled_issues.insert(1, days_ago(400));
+        repo_state.handled_issues.insert(2, days_ago(10));
+        repo_state.handled_issues.insert(3, days_ago(0));
+
+        let stats = state.gc(&config);
+        assert_eq!(stats.comments_pruned, 1);
+        assert_eq!(stats.comments_kept, 2);
+        assert_eq!(stats.issues_pruned, 1);
+        assert_eq!(stats.issues_kept, 2);
+
+        let repo_state = &state.wg_repos[WG_REPO];
+        assert!(!repo_state.handled_comments.contains_key(&comment(1)));
+        assert!(!repo_state.handled_resolutions.contains_key(&comment(1)));
+        assert!(repo_state.handled_comments.contains_key(&comment(2)));
+        assert!(repo_state.handled_resolutions.contains_key(&comment(3)));
+        let mut handled_issues = state.decisions_repos[DECISIONS_REPO]
+            .handled_issues
+            .keys()
+            .collect::<Vec<_>>();
+        handled_issues.sort();
+        assert_eq!(handled_issues, vec![&2, &3]);
+
+        // The pruned items are recorded so that the store removes them.
+        assert_eq!(state.changes.len(), 2);
+        assert!(state.changes.iter().any(|change| matches!(
+            change,
+            Change::HandledComment(repo, url) if repo == WG_REPO && *url == comment(1)
+        )));
+        assert!(state.changes.iter().any(|change| matches!(
+            change,
+            Change::HandledDecisionsIssue(repo, 1) if repo == DECISIONS_REPO
+        )));
+
+        // Nothing is left to prune the second time.
+        let stats = state.gc(&config);
+        assert_eq!((stats.comments_pruned, stats.issues_pruned), (0, 0));
+        assert_eq!((stats.comments_kept, stats.issues_kept), (2, 2));
+    }
+}
@@ -0,0 +1,56 @@
+-- The state in the fixture state file v5, as a version 5 state database.
+
+CREATE TABLE meta (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+CREATE TABLE tasks (
+    list TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    task TEXT NOT NULL,
+    PRIMARY KEY (list, position)
+);
+CREATE TABLE cursors (
+    kind TEXT NOT NULL,
+    repo TEXT NOT NULL,
+    last_time TEXT NOT NULL,
+    PRIMARY KEY (kind, repo)
+);
+CREATE TABLE handled_comments (
+    repo TEXT NOT NULL,
+    url TEXT NOT NULL,
+    resolutions TEXT,
+    handled_at TEXT NOT NULL,
+    PRIMARY KEY (repo, url)
+);
+CREATE TABLE tracking_issues (
+    wg_repo TEXT NOT NULL,
+    issue_number INTEGER NOT NULL,
+    decisions_repo TEXT NOT NULL,
+    id TEXT NOT NULL,
+    number INTEGER NOT NULL,
+    PRIMARY KEY (wg_repo, issue_number)
+);
+CREATE TABLE handled_decisions_issues (
+    repo TEXT NOT NULL,
+    number INTEGER NOT NULL,
+    handled_at TEXT NOT NULL,
+    PRIMARY KEY (repo, number)
+);
+
+INSERT INTO meta VALUES ('version', '5');
+INSERT INTO meta VALUES ('schema_version', '5');
+
+INSERT INTO tasks VALUES ('queued', 0, '{"task":{"type":"QueryWGIssuesTask","wg_repo":"w3c/csswg-drafts","since":"2019-06-01T00:00:00Z"},"attempts":0,"next_attempt":null,"last_error":null}');
+INSERT INTO tasks VALUES ('queued', 1, '{"task":{"type":"AddResolutionsTask","file_issue":{"decisions_repo":"mozilla/wg-decisions","wg_repo":"w3c/csswg-drafts","issue_number":4000,"issue_title":"[css-foo] Bar","issue_labels":["css-foo-1"],"comment_url":"https://github.com/w3c/csswg-drafts/issues/4000#issuecomment-2","resolutions":["Do the thing"],"previous_issue":null}},"attempts":1,"next_attempt":"2019-06-01T00:05:00Z","last_error":"GitHub returned 502 Bad Gateway"}');
+INSERT INTO tasks VALUES ('queued', 2, '{"task":{"type":"ProcessDecisionsIssueTask","decisions_repo":"mozilla/wg-decisions","issue_number":13,"issue_id":"MDU6SXNzdWUxMw==","issue_labels":[{"name":"bug","color":"d73a4a"}]},"attempts":0,"next_attempt":null,"last_error":null}');
+INSERT INTO tasks VALUES ('dead', 0, '{"task":{"type":"CloseIssueTask","issue_id":"MDU6SXNzdWUxMg=="},"attempts":5,"next_attempt":null,"last_error":"could not close issue"}');
+
+INSERT INTO cursors VALUES ('wg', 'w3c/csswg-drafts', '2019-06-01T00:00:00Z');
+INSERT INTO cursors VALUES ('decisions', 'mozilla/wg-decisions', '2019-05-01T00:00:00Z');
+INSERT INTO cursors VALUES ('decisions', 'w3c/css-decisions', '2019-05-01T00:00:00Z');
+
+INSERT INTO handled_comments VALUES ('w3c/csswg-drafts', 'https://github.com/w3c/csswg-drafts/issues/4000#issuecomment-1', NULL, '2019-05-30T12:00:00Z');
+INSERT INTO handled_comments VALUES ('w3c/csswg-drafts', 'https://github.com/w3c/csswg-drafts/issues/4000#issuecomment-2', '["Do the thing"]', '2019-06-01T00:02:00Z');
+INSERT INTO tracking_issues VALUES ('w3c/csswg-drafts', 4000, 'mozilla/wg-decisions', 'MDU6SXNzdWU3', 7);
+INSERT INTO handled_decisions_issues VALUES ('mozilla/wg-decisions', 12, '2019-04-20T09:30:00Z');
@@ -0,0 +1,590 @@
+//! Upgrades of state from older versions.
+//!
+//! Each time the layout of the state changes in a way that older state can't
+//! be deserialized with (including adding a required field to a task), the
+//! change needs a migration, which is added to the end of `MIGRATIONS`, and a
+//! fixture state file for the new version in `fixtures`.  Each version also
+//! needs a fixture state database in `fixtures`, `vN.sql`, holding the same
+//! state with the tables of that version, and any change to the tables needs
+//! an entry in `sqlite::SCHEMA_MIGRATIONS`.
+//!
+//! Migrations operate on the JSON value of the state.  Queued tasks in a state
+//! database are upgraded by passing them through the migrations as a value
+//! with only `tasks` and `dead_tasks`, so migrations must cope with other keys
+//! being absent.
+
+use crate::config::Config;
+use failure::{format_err, Error};
+
+/// Upgrades state from the version it's at to the next one.  State from
+/// before we supported multiple repositories is assumed to be for the first
+/// configured WG repository and the default decisions repository.
+type Migration = fn(&mut serde_json::Value, &Config);
+
+/// The migration from each version to the next, starting with version 1.
+const MIGRATIONS: &[Migration] = &[
+    upgrade_v1, upgrade_v2, upgrade_v3, upgrade_v4, upgrade_v5, upgrade_v6,
+];
+
+/// The version number written at the top of state files.
+pub const STATE_VERSION: u32 = MIGRATIONS.len() as u32 + 1;
+
+/// Upgrades state of the given version to the current version.
+pub fn migrate(value: &mut serde_json::Value, version: u32, config: &Config) -> Result<(), Error> {
+    if version == 0 || version > STATE_VERSION {
+        return Err(format_err!("unknown state version number {}", version));
+    }
+    for migration in &MIGRATIONS[version as usize - 1..] {
+        migration(value, config);
+    }
+    Ok(())
+}
+
+/// Upgrades a version 1 state file, which stored bare tasks in the queue
+/// without any retry information.
+fn upgrade_v1(value: &mut serde_json::Value, _config: &Config) {
+    if let Some(tasks) = value.get_mut("tasks").and_then(|t| t.as_array_mut()) {
+        for task in tasks.iter_mut() {
+            *task = serde_json::json!({ "task": task.take() });
+        }
+    }
+}
+
+/// Upgrades a version 2 state file, which only supported a single WG
+/// repository.
+fn upgrade_v2(value: &mut serde_json::Value, config: &Config) {
+    let wg_repo = &config.wg_repos[0].id();
+    let object = match value.as_object_mut() {
+        Some(object) => object,
+        None => return,
+    };
+
+    let mut take = |key, default: serde_json::Value| object.remove(key).unwrap_or(default);
+    let repo_state = serde_json::json!({
+        "last_time": take("last_time_wg", serde_json::Value::Null),
+        "handled_comments": take("handled_wg_comments", serde_json::json!([])),
+        "handled_resolutions": take("handled_wg_resolutions", serde_json::json!({})),
+        "tracking_issues": take("tracking_issues", serde_json::json!({})),
+    });
+    object.insert(
+        String::from("wg_repos"),
+        serde_json::json!({ wg_repo: repo_state }),
+    );
+
+    // Tasks that deal with WG issues now need to know which repository
+    // they are for.
+    for_each_task(object, |task_type, task| match task_type {
+        "QueryWGIssuesTask"
+        | "QueryWGIssueTask"
+        | "QueryWGIssueCommentsTask"
+        | "ProcessWGCommentTask"
+        | "FileIssueTask" => {
+            task.insert(String::from("wg_repo"), serde_json::json!(wg_repo));
+        }
+        _ => {}
+    });
+}
+
+/// Upgrades a version 3 state file, which only supported a single decisions
+/// repository.
+fn upgrade_v3(value: &mut serde_json::Value, config: &Config) {
+    let decisions_repo = &config.default_decisions_repo();
+    let object = match value.as_object_mut() {
+        Some(object) => object,
+        None => return,
+    };
+
+    let mut take = |key, default: serde_json::Value| object.remove(key).unwrap_or(default);
+    let repo_state = serde_json::json!({
+        "last_time": take("last_time_decisions", serde_json::Value::Null),
+        "handled_issues": take("handled_decisions_issues", serde_json::json!([])),
+    });
+    object.insert(
+        String::from("decisions_repos"),
+        serde_json::json!({ decisions_repo: repo_state }),
+    );
+
+    let tracking_issues = object
+        .get_mut("wg_repos")
+        .and_then(|r| r.as_object_mut())
+        .into_iter()
+        .flat_map(|r| r.values_mut())
+        .filter_map(|r| r.get_mut("tracking_issues").and_then(|t| t.as_object_mut()))
+        .flat_map(|t| t.values_mut())
+        .filter_map(|t| t.as_object_mut());
+    for tracking_issue in tracking_issues {
+        tracking_issue.insert(
+            String::from("decisions_repo"),
+            serde_json::json!(decisions_repo),
+        );
+    }
+
+    // Tasks that deal with decisions issues now need to know which
+    // repository they are for.
+    for_each_task(object, |task_type, task| match task_type {
+        "QueryDecisionsIssuesTask"
+        | "QueryDecisionsKnownLabelsTask"
+        | "EnsureLabelTask"
+        | "QueryDecisionsRepoID"
+        | "FileIssueTask"
+        | "RemoveDecisionsIssueBugLabelTask"
+        | "FileBugForDecisionsIssueTask" => {
+            task.insert(
+                String::from("decisions_repo"),
+                serde_json::json!(decisions_repo),
+            );
+        }
+        _ => {}
+    });
+}
+
+/// Upgrades a version 4 state file, which didn't record when comments and
+/// decisions issues were handled.  They are treated as having been handled
+/// at the time of their repository's cursor.
+fn upgrade_v4(value: &mut serde_json::Value, _config: &Config) {
+    for (repos_key, handled_key) in &[
+        ("wg_repos", "handled_comments"),
+        ("decisions_repos", "handled_issues"),
+    ] {
+        let repo_states = value
+            .get_mut(*repos_key)
+            .and_then(|r| r.as_object_mut())
+            .into_iter()
+            .flat_map(|r| r.values_mut())
+            .filter_map(|r| r.as_object_mut());
+        for repo_state in repo_states {
+            let last_time = repo_state
+                .get("last_time")
+                .and_then(|t| t.as_str())
+                .unwrap_or_default()
+                .to_string();
+            let handled = match repo_state.get(*handled_key).and_then(|h| h.as_array()) {
+                Some(handled) => handled
+                    .iter()
+                    .map(|h| match h {
+                        serde_json::Value::String(s) => s.clone(),
+                        h => h.to_string(),
+                    })
+                    .map(|h| (h, serde_json::json!(last_time)))
+                    .collect::<JsonObject>(),
+                None => continue,
+            };
+            repo_state.insert(handled_key.to_string(), serde_json::Value::Object(handled));
+        }
+    }
+}
+
+/// Upgrades a version 5 state file, which only looked at issues in WG
+/// repositories, not pull requests or discussions.  Those are looked at from
+/// the time of the repository's issue cursor, rather than going through
+/// their whole history.
+fn upgrade_v5(value: &mut serde_json::Value, _config: &Config) {
+    let object = match value.as_object_mut() {
+        Some(object) => object,
+        None => return,
+    };
+
+    let repo_states = object
+        .get_mut("wg_repos")
+        .and_then(|r| r.as_object_mut())
+        .into_iter()
+        .flat_map(|r| r.values_mut())
+        .filter_map(|r| r.as_object_mut());
+    for repo_state in repo_states {
+        let last_time = repo_state
+            .get("last_time")
+            .cloned()
+            .unwrap_or(serde_json::Value::Null);
+        repo_state.insert(String::from("last_pull_request_time"), last_time.clone());
+        repo_state.insert(String::from("last_discussion_time"), last_time);
+    }
+
+    for_each_task(object, |task_type, task| match task_type {
+        "QueryWGIssuesTask"
+        | "QueryWGIssueCommentsTask"
+        | "ProcessWGCommentTask"
+        | "FileIssueTask" => {
+            task.insert(String::from("kind"), serde_json::json!("issue"));
+        }
+        _ => {}
+    });
+}
+
+/// Upgrades a version 6 state file, which kept the tracking issues for WG
+/// issues, pull requests and discussions together, keyed by number.  Each is
+/// moved to the map for its kind, which we find from the URLs of the comments
+/// handled for that number, assuming it is an issue if there are none.
+fn upgrade_v6(value: &mut serde_json::Value, _config: &Config) {
+    let repo_states = value
+        .get_mut("wg_repos")
+        .and_then(|r| r.as_object_mut())
+        .into_iter()
+        .flat_map(|r| r.values_mut())
+        .filter_map(|r| r.as_object_mut());
+    for repo_state in repo_states {
+        let urls = repo_state
+            .get("handled_comments")
+            .and_then(|c| c.as_object())
+            .map(|c| c.keys().cloned().collect::<Vec<_>>())
+            .unwrap_or_default();
+        let mut pull_request_tracking_issues = JsonObject::new();
+        let mut discussion_tracking_issues = JsonObject::new();
+        if let Some(tracking_issues) = repo_state
+            .get_mut("tracking_issues")
+            .and_then(|t| t.as_object_mut())
+        {
+            let numbers = tracking_issues.keys().cloned().collect::<Vec<_>>();
+            for number in numbers {
+                let has_url = |path: &str| {
+                    let needle = format!("/{}/{}#", path, number);
+                    urls.iter().any(|url| url.contains(&needle))
+                };
+                let map = if has_url("pull") {
+                    &mut pull_request_tracking_issues
+                } else if has_url("discussions") {
+                    &mut discussion_tracking_issues
+                } else {
+                    continue;
+                };
+                let tracking_issue = tracking_issues.remove(&number).unwrap();
+                map.insert(number, tracking_issue);
+            }
+        }
+        repo_state.insert(
+            String::from("pull_request_tracking_issues"),
+            pull_request_tracking_issues.into(),
+        );
+        repo_state.insert(
+            String::from("discussion_tracking_issues"),
+            discussion_tracking_issues.into(),
+        );
+    }
+}
+
+type JsonObject = serde_json::Map<String, serde_json::Value>;
+
+/// Calls `f` with the type and fields of every task in a state file,
+/// including the `FileIssueTask` inside each `AddResolutionsTask`.
+fn for_each_task<F>(object: &mut JsonObject, mut f: F)
+where
+    F: FnMut(&str, &mut JsonObject),
+{
+    let mut visit = |task: &mut serde_json::Value| {
+        let task = match task.as_object_mut() {
+            Some(task) => task,
+            None => return,
+        };
+        let task_type = match task.get("type").and_then(|t| t.as_str()) {
+            Some(task_type) => task_type.to_string(),
+            None => return,
+        };
+        if task_type == "AddResolutionsTask" {
+            if let Some(file_issue) = task.get_mut("file_issue").and_then(|t| t.as_object_mut()) {
+                f("FileIssueTask", file_issue);
+            }
+        }
+        f(&task_type, task);
+    };
+    for key in &["tasks", "dead_tasks"] {
+        if let Some(tasks) = object.get_mut(*key).and_then(|t| t.as_array_mut()) {
+            for task in tasks {
+                visit(&mut task["task"]);
+            }
+        }
+    }
+    if let Some(tasks) = object
+        .get_mut("posted_tasks")
+        .and_then(|t| t.as_array_mut())
+    {
+        for task in tasks {
+            visit(task);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::sqlite::Database;
+    use crate::state::VersionedState;
+    use crate::tests::StateDirectory;
+    use rusqlite::Connection;
+    use serde_json::{json, Value};
+    use std::fs;
+    use std::path::PathBuf;
+
+    const CONFIG: &str = r#"
+        github_key = ""
+        bugzilla_key = ""
+        wg_repo_owner = "w3c"
+        wg_repo_name = "csswg-drafts"
+        decisions_repo_owner = "mozilla"
+        decisions_repo_name = "wg-decisions"
+        state_directory = ""
+        start_date = "2019-01-01"
+    "#;
+
+    fn fixture_path(version: u32) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("src/state/fixtures")
+            .join(format!("v{}", version))
+    }
+
+    /// Loads the fixture state file for a version, upgrading it to the
+    /// current version, and returns the result as it would be saved.
+    fn load(version: u32) -> Value {
+        let config = Config::from_toml(CONFIG).unwrap();
+        let state = VersionedState::from_path(&fixture_path(version), &config).unwrap();
+        let mut value = serde_json::to_value(&*state).unwrap();
+        normalize(&mut value);
+        value
+    }
+
+    /// The first version whose state could be saved in a database.
+    const FIRST_DATABASE_VERSION: u32 = 4;
+
+    /// Creates a database from the fixture state database for a version,
+    /// upgrading it to the current version, and returns its state as it would
+    /// be saved.
+    fn load_database(version: u32) -> Value {
+        let config = Config::from_toml(CONFIG).unwrap();
+        let directory = StateDirectory::new(&format!("fixture-database-v{}", version));
+        let path = directory.0.join("state.db");
+        let sql = fs::read_to_string(fixture_path(version).with_extension("sql")).unwrap();
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch(&sql)
+            .unwrap();
+        let state = Database::open(&path).unwrap().load(&config).unwrap();
+        let mut value = serde_json::to_value(&state).unwrap();
+        normalize(&mut value);
+        value
+    }
+
+    /// Sorts arrays of strings and numbers, since sets are saved in no
+    /// particular order.
+    fn normalize(value: &mut Value) {
+        match value {
+            Value::Array(items) => {
+                items.iter_mut().for_each(normalize);
+                if items.iter().all(|i| i.is_string() || i.is_number()) {
+                    items.sort_by_key(|i| i.to_string());
+                }
+            }
+            Value::Object(object) => object.values_mut().for_each(normalize),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn every_version_has_a_fixture() {
+        for version in 1..=STATE_VERSION {
+            load(version);
+        }
+    }
+
+    #[test]
+    fn current_version_is_unchanged() {
+        let contents = fs::read_to_string(fixture_path(STATE_VERSION)).unwrap();
+        let json = &contents[contents.find('\n').unwrap() + 1..];
+        let mut expected: Value = serde_json::from_str(json).unwrap();
+        normalize(&mut expected);
+        assert_eq!(load(STATE_VERSION), expected);
+    }
+
+    #[test]
+    fn database_fixtures_upgrade_to_the_same_state() {
+        for version in FIRST_DATABASE_VERSION..=STATE_VERSION {
+            assert_eq!(
+                load_database(version),
+                load(version),
+                "fixture database for version {} differs",
+                version
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_versions_are_rejected() {
+        let config = Config::from_toml(CONFIG).unwrap();
+        assert!(migrate(&mut json!({}), 0, &config).is_err());
+        assert!(migrate(&mut json!({}), STATE_VERSION + 1, &config).is_err());
+    }
+
+    #[test]
+    fn v1_tasks_get_retry_information_and_repositories() {
+        let state = load(1);
+        assert_eq!(
+            state["tasks"][0],
+            json!({
+                "task": {
+                    "type": "QueryWGIssuesTask",
+                    "wg_repo": "w3c/csswg-drafts",
+                    "kind": "issue",
+                    "since": "2019-06-01T00:00:00Z",
+                },
+                "attempts": 0,
+                "next_attempt": null,
+                "last_error": null,
+            })
+        );
+        assert_eq!(state["tasks"][1]["task"]["wg_repo"], "w3c/csswg-drafts");
+        let file_issue = &state["tasks"][2]["task"];
+        assert_eq!(file_issue["wg_repo"], "w3c/csswg-drafts");
+        assert_eq!(file_issue["decisions_repo"], "mozilla/wg-decisions");
+        assert_eq!(
+            state["tasks"][3]["task"],
+            json!({
+                "type": "QueryDecisionsKnownLabelsTask",
+                "decisions_repo": "mozilla/wg-decisions",
+            })
+        );
+        assert_eq!(
+            state["tasks"][4]["task"]["decisions_repo"],
+            "mozilla/wg-decisions"
+        );
+        assert_eq!(
+            state["posted_tasks"][0]["decisions_repo"],
+            "mozilla/wg-decisions"
+        );
+    }
+
+    #[test]
+    fn v1_handled_items_move_to_repositories() {
+        let state = load(1);
+        assert_eq!(
+            state["wg_repos"],
+            json!({
+                "w3c/csswg-drafts": {
+                    "last_time": "2019-06-01T00:00:00Z",
+                    "last_pull_request_time": "2019-06-01T00:00:00Z",
+                    "last_discussion_time": "2019-06-01T00:00:00Z",
+                    "handled_comments": {
+                        "https://github.com/w3c/csswg-drafts/issues/3999#issuecomment-1":
+                            "2019-06-01T00:00:00Z",
+                        "https://github.com/w3c/csswg-drafts/issues/4000#issuecomment-1":
+                            "2019-06-01T00:00:00Z",
+                    },
+                    "handled_resolutions": {},
+                    "tracking_issues": {},
+                    "pull_request_tracking_issues": {},
+                    "discussion_tracking_issues": {},
+                },
+            })
+        );
+        assert_eq!(
+            state["decisions_repos"],
+            json!({
+                "mozilla/wg-decisions": {
+                    "last_time": "2019-05-01T00:00:00Z",
+                    "handled_issues": {
+                        "10": "2019-05-01T00:00:00Z",
+                        "12": "2019-05-01T00:00:00Z",
+                    },
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn v2_keeps_retry_information_and_upgrades_dead_tasks() {
+        let state = load(2);
+        let task = &state["tasks"][1];
+        assert_eq!(task["task"]["wg_repo"], "w3c/csswg-drafts");
+        assert_eq!(task["attempts"], 2);
+        assert_eq!(task["next_attempt"], "2019-06-01T00:10:00Z");
+        assert_eq!(task["last_error"], "GitHub returned 502 Bad Gateway");
+        assert_eq!(
+            state["tasks"][2]["task"]["decisions_repo"],
+            "mozilla/wg-decisions"
+        );
+        assert_eq!(
+            state["posted_tasks"][0],
+            json!({
+                "type": "QueryDecisionsRepoID",
+                "decisions_repo": "mozilla/wg-decisions",
+            })
+        );
+        let dead_task = &state["dead_tasks"][0];
+        assert_eq!(dead_task["task"]["wg_repo"], "w3c/csswg-drafts");
+        assert_eq!(dead_task["task"]["decisions_repo"], "mozilla/wg-decisions");
+        assert_eq!(dead_task["attempts"], 5);
+        assert_eq!(
+            state["wg_repos"]["w3c/csswg-drafts"]["handled_resolutions"],
+            json!({
+                "https://github.com/w3c/csswg-drafts/issues/4000#issuecomment-1": [
+                    "Do the other thing",
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn v3_tracking_issues_and_nested_tasks_get_decisions_repository() {
+        let state = load(3);
+        let wg_repo = &state["wg_repos"]["w3c/fxtf-drafts"];
+        assert_eq!(
+            wg_repo["tracking_issues"]["300"],
+            json!({
+                "decisions_repo": "mozilla/wg-decisions",
+                "id": "MDU6SXNzdWU3",
+                "number": 7,
+            })
+        );
+        let file_issue = &state["tasks"][1]["task"]["file_issue"];
+        assert_eq!(file_issue["wg_repo"], "w3c/fxtf-drafts");
+        assert_eq!(file_issue["decisions_repo"], "mozilla/wg-decisions");
+        assert_eq!(file_issue["previous_issue"], 7);
+        assert_eq!(
+            state["tasks"][2]["task"]["decisions_repo"],
+            "mozilla/wg-decisions"
+        );
+        assert_eq!(
+            state["decisions_repos"]["mozilla/wg-decisions"]["handled_issues"],
+            json!({ "12": "2019-05-01T00:00:00Z" })
+        );
+    }
+
+    #[test]
+    fn v4_handled_items_get_cursor_times() {
+        let state = load(4);
+        assert_eq!(
+            state["wg_repos"]["w3c/csswg-drafts"]["handled_comments"],
+            json!({
+                "https://github.com/w3c/csswg-drafts/issues/4000#issuecomment-1":
+                    "2019-06-01T00:00:00Z",
+                "https://github.com/w3c/csswg-drafts/issues/4000#issuecomment-2":
+                    "2019-06-01T00:00:00Z",
+            })
+        );
+        assert_eq!(
+            state["decisions_repos"]["mozilla/wg-decisions"]["handled_issues"],
+            json!({ "12": "2019-05-01T00:00:00Z" })
+        );
+        assert_eq!(
+            state["decisions_repos"]["w3c/css-decisions"]["handled_issues"],
+            json!({})
+        );
+    }
+
+    #[test]
+    fn v5_wg_tasks_are_for_issues_and_other_kinds_start_at_cursor() {
+        let state = load(5);
+        let wg_repo = &state["wg_repos"]["w3c/csswg-drafts"];
+        assert_eq!(wg_repo["last_pull_request_time"], "2019-06-01T00:00:00Z");
+        assert_eq!(wg_repo["last_discussion_time"], "2019-06-01T00:00:00Z");
+        assert_eq!(state["tasks"][0]["task"]["kind"], "issue");
+        assert_eq!(state["tasks"][1]["task"]["file_issue"]["kind"], "issue");
+        assert!(state["tasks"][2]["task"].get("kind").is_none());
+    }
+
+    #[test]
+    fn v6_tracking_issues_are_split_by_kind() {
+        let state = load(6);
+        let wg_repo = &state["wg_repos"]["w3c/csswg-drafts"];
+        assert_eq!(wg_repo["tracking_issues"]["4000"]["number"], 7);
+        assert!(wg_repo["tracking_issues"].get("4001").is_none());
+        assert_eq!(wg_repo["pull_request_tracking_issues"]["4001"]["number"], 8);
+        assert_eq!(wg_repo["discussion_tracking_issues"], json!({}));
+    }
+}
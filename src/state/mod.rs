@@ -1,12 +1,33 @@
 mod current;
+mod migrations;
+mod snapshots;
+mod sqlite;
 
+use crate::config::Config;
+use crate::util::print_info;
 use failure::{format_err, Error, ResultExt};
-use std::fs::File;
+use flate2::read::GzDecoder;
+use sqlite::Database;
+use std::fs::{self, File};
 use std::io::Read;
 use std::ops::{Deref, DerefMut};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 pub use current::State;
+pub use migrations::STATE_VERSION;
+
+/// How the state is stored.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StateBackend {
+    /// A single JSON file, `state`, rewritten in full on every save.
+    Json,
+    /// An SQLite database, `state.db`.  If there is a JSON state file but no
+    /// database, the JSON state file is imported into a new database the
+    /// first time the state is saved, and renamed to `state.imported`.
+    #[default]
+    Sqlite,
+}
 
 #[derive(Default)]
 pub struct VersionedState(State);
@@ -26,27 +47,173 @@ impl DerefMut for VersionedState {
 }
 
 impl VersionedState {
-    pub fn new(date: &str) -> VersionedState {
-        // FIXME Use a better type for date or assert the value is valid.
-        VersionedState(State::new(date))
+    pub fn new() -> VersionedState {
+        VersionedState(State::new())
     }
 
-    pub fn from_path(path: &Path) -> Result<VersionedState, Error> {
-        let mut contents = String::new();
+    /// Loads a state file, or a gzipped one such as a snapshot.
+    pub fn from_path(path: &Path, config: &Config) -> Result<VersionedState, Error> {
+        let mut bytes = Vec::new();
         File::open(path)
             .context("could not open state file")?
-            .read_to_string(&mut contents)
+            .read_to_end(&mut bytes)
             .context("could not read state file")?;
 
+        let mut contents = String::new();
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            GzDecoder::new(&bytes[..])
+                .read_to_string(&mut contents)
+                .context("could not decompress state file")?;
+        } else {
+            contents = String::from_utf8(bytes).context("could not read state file")?;
+        }
+
         match contents.find('\n') {
             Some(i) => {
                 let version = contents[0..i]
                     .parse::<u32>()
                     .context("could not parse version number in state file")?;
                 let json = &contents[i + 1..];
-                State::from_versioned_str(version, json).map(VersionedState)
+                State::from_versioned_str(version, json, config).map(VersionedState)
             }
             None => Err(format_err!("could not find version number in state file")),
         }
     }
 }
+
+/// Where the state is loaded from and saved to.
+pub struct StateStore {
+    backend: StateBackend,
+    state_directory: PathBuf,
+    json_path: PathBuf,
+    json_temp_path: PathBuf,
+    imported_json_path: PathBuf,
+    database_path: PathBuf,
+    database: Option<Database>,
+}
+
+impl StateStore {
+    pub fn new(state_directory: &str, backend: StateBackend) -> StateStore {
+        let state_directory_path = Path::new(state_directory);
+        StateStore {
+            backend,
+            state_directory: state_directory_path.to_path_buf(),
+            json_path: state_directory_path.join("state"),
+            json_temp_path: state_directory_path.join("state.temp"),
+            imported_json_path: state_directory_path.join("state.imported"),
+            database_path: state_directory_path.join("state.db"),
+            database: None,
+        }
+    }
+
+    /// Loads the state.  If `read_only` is true, nothing is written, and the
+    /// state must not be saved afterwards.
+    pub fn load(&mut self, config: &Config, read_only: bool) -> Result<VersionedState, Error> {
+        if self.backend == StateBackend::Sqlite && self.database_path.exists() {
+            let database = if read_only {
+                Database::open_read_only(&self.database_path)?
+            } else {
+                Database::open(&self.database_path)?
+            };
+            let state = database.load(config)?;
+            self.database = Some(database);
+            return Ok(VersionedState(state));
+        }
+        if self.json_path.exists() {
+            VersionedState::from_path(&self.json_path, config)
+        } else {
+            Ok(VersionedState::new())
+        }
+    }
+
+    pub fn save(&mut self, state: &mut VersionedState) -> Result<(), Error> {
+        if self.backend == StateBackend::Json {
+            state.save(&self.json_path, &self.json_temp_path)?;
+            state.changes.clear();
+            return Ok(());
+        }
+
+        if let Some(database) = &mut self.database {
+            return database.save(state);
+        }
+
+        // This is the first save into a new database, so write everything,
+        // including anything we loaded from the JSON state file.
+        let mut database = Database::open(&self.database_path)?;
+        sqlite::mark_all_changed(state);
+        database.save(state)?;
+        self.database = Some(database);
+        if self.json_path.exists() {
+            fs::rename(&self.json_path, &self.imported_json_path)
+                .context("could not rename imported state file")?;
+            print_info(&format!(
+                "imported state file into {}",
+                self.database_path.display()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the size in bytes of the saved state.
+    pub fn size(&self) -> Result<u64, Error> {
+        let path = match self.backend {
+            StateBackend::Json => &self.json_path,
+            StateBackend::Sqlite => &self.database_path,
+        };
+        if !path.exists() {
+            return Ok(0);
+        }
+        Ok(fs::metadata(path)
+            .context("could not read state file size")?
+            .len())
+    }
+
+    /// Reclaims space left by removed state, if the backend doesn't do so
+    /// when saving.
+    pub fn compact(&self) -> Result<(), Error> {
+        match &self.database {
+            Some(database) => database.compact(),
+            None => Ok(()),
+        }
+    }
+
+    /// Replaces the saved state entirely, rather than just saving what has
+    /// changed.
+    pub fn replace(&mut self, state: &mut VersionedState) -> Result<(), Error> {
+        match &mut self.database {
+            Some(database) => database.replace(state),
+            None => self.save(state),
+        }
+    }
+
+    /// Writes a snapshot of the state, keeping only the newest `keep`
+    /// snapshots.
+    pub fn snapshot(&self, state: &State, keep: usize) -> Result<(), Error> {
+        if keep > 0 {
+            snapshots::write(&self.state_directory, state, keep)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the path of a snapshot, given its name or path.
+    pub fn snapshot_path(&self, snapshot: &str) -> Result<PathBuf, Error> {
+        let path = Path::new(snapshot);
+        if path.exists() {
+            return Ok(path.to_path_buf());
+        }
+        let path = snapshots::directory(&self.state_directory).join(snapshot);
+        if path.exists() {
+            return Ok(path);
+        }
+        let names = snapshots::list(&self.state_directory)?;
+        Err(format_err!(
+            "could not find snapshot {}; available snapshots are: {}",
+            snapshot,
+            if names.is_empty() {
+                String::from("none")
+            } else {
+                names.join(", ")
+            }
+        ))
+    }
+}
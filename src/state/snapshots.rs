@@ -0,0 +1,175 @@
+use super::{State, STATE_VERSION};
+use chrono::Utc;
+use failure::{Error, ResultExt};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Snapshots are gzipped state files, in a `snapshots` directory inside the
+/// state directory, named after the time they were taken so that they sort
+/// oldest first.
+const PREFIX: &str = "state-";
+const SUFFIX: &str = ".gz";
+
+pub fn directory(state_directory: &Path) -> PathBuf {
+    state_directory.join("snapshots")
+}
+
+/// Writes a snapshot of the state, and removes all but the newest `keep`
+/// snapshots.
+pub fn write(state_directory: &Path, state: &State, keep: usize) -> Result<(), Error> {
+    let directory = directory(state_directory);
+    fs::create_dir_all(&directory).context("could not create snapshot directory")?;
+
+    let name = format!(
+        "{}{}{}",
+        PREFIX,
+        Utc::now().format("%Y%m%dT%H%M%S%.3fZ"),
+        SUFFIX
+    );
+    let path = directory.join(&name);
+    let temp_path = directory.join(format!("{}.temp", name));
+    {
+        let file = File::create(&temp_path).context("could not create snapshot")?;
+        let mut encoder = GzEncoder::new(file, Compression::default());
+        writeln!(encoder, "{}", STATE_VERSION).context("could not write snapshot")?;
+        serde_json::to_writer(&mut encoder, state).context("could not write snapshot")?;
+        encoder.finish().context("could not write snapshot")?;
+    }
+    fs::rename(&temp_path, &path).context("could not write snapshot")?;
+
+    let snapshots = list(state_directory)?;
+    if snapshots.len() > keep {
+        for old in &snapshots[..snapshots.len() - keep] {
+            fs::remove_file(directory.join(old)).context("could not remove old snapshot")?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the names of the snapshots, oldest first.
+pub fn list(state_directory: &Path) -> Result<Vec<String>, Error> {
+    let directory = directory(state_directory);
+    if !directory.exists() {
+        return Ok(Vec::new());
+    }
+    let mut names = Vec::new();
+    for entry in fs::read_dir(&directory).context("could not read snapshot directory")? {
+        let name = entry
+            .context("could not read snapshot directory")?
+            .file_name()
+            .to_string_lossy()
+            .into_owned();
+        if name.starts_with(PREFIX) && name.ends_with(SUFFIX) {
+            names.push(name);
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::state::VersionedState;
+    use crate::tests::StateDirectory;
+    use flate2::write::GzEncoder;
+
+    const CONFIG: &str = r#"
+        github_key = ""
+        bugzilla_key = ""
+        wg_repo_owner = "w3c"
+        wg_repo_name = "csswg-drafts"
+        decisions_repo_owner = "mozilla"
+        decisions_repo_name = "wg-decisions"
+        state_directory = ""
+        start_date = "2019-01-01"
+    "#;
+
+    fn config() -> Config {
+        Config::from_toml(CONFIG).unwrap()
+    }
+
+    fn fixture() -> VersionedState {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("src/state/fixtures")
+            .join(format!("v{}", STATE_VERSION));
+        VersionedState::from_path(&path, &config()).unwrap()
+    }
+
+    fn write_gzipped(path: &Path, contents: &str) {
+        let mut encoder = GzEncoder::new(File::create(path).unwrap(), Compression::default());
+        encoder.write_all(contents.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+    }
+
+    #[test]
+    fn snapshots_load_as_the_state() {
+        let state_directory = StateDirectory::new("snapshots-load");
+        let state = fixture();
+        write(&state_directory.0, &state, 1).unwrap();
+
+        let names = list(&state_directory.0).unwrap();
+        assert_eq!(names.len(), 1);
+        let loaded =
+            VersionedState::from_path(&directory(&state_directory.0).join(&names[0]), &config())
+                .unwrap();
+        assert_eq!(
+            serde_json::to_value(&*loaded).unwrap(),
+            serde_json::to_value(&*state).unwrap()
+        );
+    }
+
+    #[test]
+    fn oldest_snapshots_are_removed() {
+        let state_directory = StateDirectory::new("snapshots-rotate");
+        let directory = directory(&state_directory.0);
+        fs::create_dir_all(&directory).unwrap();
+        for name in &[
+            "state-20190101T000000.000Z.gz",
+            "state-20190102T000000.000Z.gz",
+            "state-20190103T000000.000Z.gz",
+            "notes.txt",
+        ] {
+            File::create(directory.join(name)).unwrap();
+        }
+
+        write(&state_directory.0, &State::default(), 2).unwrap();
+
+        let names = list(&state_directory.0).unwrap();
+        assert_eq!(names.len(), 2);
+        assert_eq!(names[0], "state-20190103T000000.000Z.gz");
+        assert!(names[1] > names[0]);
+        assert!(!directory.join("state-20190101T000000.000Z.gz").exists());
+        assert!(!directory.join("state-20190102T000000.000Z.gz").exists());
+        assert!(directory.join("notes.txt").exists());
+    }
+
+    #[test]
+    fn unparsable_snapshots_are_rejected() {
+        let state_directory = StateDirectory::new("snapshots-unparsable");
+        let path = state_directory.0.join("state-20190101T000000.000Z.gz");
+
+        for contents in &[
+            "",
+            "not a version\n{}",
+            &format!("{}\nnot json", STATE_VERSION),
+            &format!("{}\n{{\"wg_repos\": 1}}", STATE_VERSION),
+            &format!("{}\n{{}}", STATE_VERSION + 1),
+        ] {
+            write_gzipped(&path, contents);
+            assert!(
+                VersionedState::from_path(&path, &config()).is_err(),
+                "{:?}",
+                contents
+            );
+        }
+
+        fs::write(&path, b"\x1f\x8b truncated").unwrap();
+        assert!(VersionedState::from_path(&path, &config()).is_err());
+    }
+}
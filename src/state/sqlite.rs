@@ -0,0 +1,753 @@
+use super::current::{Change, QueuedTask, State, TrackingIssue};
+use super::migrations::{self, STATE_VERSION};
+use crate::config::Config;
+use crate::query::IssueKind;
+use failure::{format_err, Error, ResultExt};
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension, NO_PARAMS};
+use std::path::Path;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS meta (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+    -- The queued and dead tasks, in order, each as a JSON object.
+    CREATE TABLE IF NOT EXISTS tasks (
+        list TEXT NOT NULL,
+        position INTEGER NOT NULL,
+        task TEXT NOT NULL,
+        PRIMARY KEY (list, position)
+    );
+    -- The time up to which we have seen updates, for each WG ('wg') and
+    -- decisions ('decisions') repository, and for the pull requests
+    -- ('wg-pull-requests') and discussions ('wg-discussions') in each WG
+    -- repository.
+    CREATE TABLE IF NOT EXISTS cursors (
+        kind TEXT NOT NULL,
+        repo TEXT NOT NULL,
+        last_time TEXT NOT NULL,
+        PRIMARY KEY (kind, repo)
+    );
+    -- The resolutions are a JSON array, or NULL for comments handled before
+    -- we recorded them.
+    CREATE TABLE IF NOT EXISTS handled_comments (
+        repo TEXT NOT NULL,
+        url TEXT NOT NULL,
+        resolutions TEXT,
+        handled_at TEXT NOT NULL,
+        PRIMARY KEY (repo, url)
+    );
+    -- The kind is 'issue', 'pull_request' or 'discussion'.
+    CREATE TABLE IF NOT EXISTS tracking_issues (
+        wg_repo TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        issue_number INTEGER NOT NULL,
+        decisions_repo TEXT NOT NULL,
+        id TEXT NOT NULL,
+        number INTEGER NOT NULL,
+        PRIMARY KEY (wg_repo, kind, issue_number)
+    );
+    CREATE TABLE IF NOT EXISTS handled_decisions_issues (
+        repo TEXT NOT NULL,
+        number INTEGER NOT NULL,
+        handled_at TEXT NOT NULL,
+        PRIMARY KEY (repo, number)
+    );
+";
+
+/// The changes to the tables in each state version, which are applied to
+/// databases created by older versions.  `SCHEMA` creates the current
+/// tables.
+const SCHEMA_MIGRATIONS: &[(u32, &str)] = &[
+    (
+        5,
+        "
+    -- Entries handled before we recorded when are treated as having been
+    -- handled at the time of the repository's cursor.
+    ALTER TABLE handled_comments ADD COLUMN handled_at TEXT NOT NULL DEFAULT '';
+    UPDATE handled_comments SET handled_at = COALESCE(
+        (SELECT last_time FROM cursors WHERE kind = 'wg' AND repo = handled_comments.repo),
+        ''
+    );
+    ALTER TABLE handled_decisions_issues ADD COLUMN handled_at TEXT NOT NULL DEFAULT '';
+    UPDATE handled_decisions_issues SET handled_at = COALESCE(
+        (SELECT last_time FROM cursors
+         WHERE kind = 'decisions' AND repo = handled_decisions_issues.repo),
+        ''
+    );
+    ",
+    ),
+    (
+        6,
+        "
+    -- Pull requests and discussions in WG repositories are looked at from
+    -- the time of the repository's issue cursor.
+    INSERT OR IGNORE INTO cursors (kind, repo, last_time)
+        SELECT 'wg-pull-requests', repo, last_time FROM cursors WHERE kind = 'wg';
+    INSERT OR IGNORE INTO cursors (kind, repo, last_time)
+        SELECT 'wg-discussions', repo, last_time FROM cursors WHERE kind = 'wg';
+    ",
+    ),
+    (
+        7,
+        "
+    -- Tracking issues are keyed by the kind of WG item too.  The kind of
+    -- existing ones is taken from the URLs of the comments handled for that
+    -- number, if there are any left, and is otherwise assumed to be 'issue'.
+    ALTER TABLE tracking_issues RENAME TO old_tracking_issues;
+    CREATE TABLE tracking_issues (
+        wg_repo TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        issue_number INTEGER NOT NULL,
+        decisions_repo TEXT NOT NULL,
+        id TEXT NOT NULL,
+        number INTEGER NOT NULL,
+        PRIMARY KEY (wg_repo, kind, issue_number)
+    );
+    INSERT INTO tracking_issues
+        SELECT wg_repo,
+               COALESCE(
+                   (SELECT CASE
+                        WHEN url LIKE '%/pull/' || t.issue_number || '#%' THEN 'pull_request'
+                        ELSE 'discussion'
+                    END
+                    FROM handled_comments
+                    WHERE repo = t.wg_repo
+                      AND (url LIKE '%/pull/' || t.issue_number || '#%'
+                           OR url LIKE '%/discussions/' || t.issue_number || '#%')
+                    LIMIT 1),
+                   'issue'
+               ),
+               issue_number, decisions_repo, id, number
+        FROM old_tracking_issues AS t;
+    DROP TABLE old_tracking_issues;
+    ",
+    ),
+];
+
+/// A state database.  Each save is done in a single transaction, and only
+/// writes the parts of the state that grow without bound if they have
+/// changed.
+pub struct Database {
+    connection: Connection,
+    /// The version of the queued tasks in the database.  They are upgraded
+    /// when loaded, and written back in the current version when saved.
+    version: u32,
+}
+
+impl Database {
+    /// Opens the database at `path`, creating it if it doesn't exist, and
+    /// upgrades its tables if they are from an older version.
+    pub fn open(path: &Path) -> Result<Database, Error> {
+        let mut connection = Connection::open(path).context("could not open state database")?;
+        connection
+            .execute_batch(SCHEMA)
+            .context("could not create state database tables")?;
+        let (version, schema_version) = read_versions(&connection)?;
+
+        let transaction = connection
+            .transaction()
+            .context("could not upgrade state database tables")?;
+        for (migration_version, sql) in SCHEMA_MIGRATIONS {
+            if *migration_version > schema_version {
+                transaction
+                    .execute_batch(sql)
+                    .context("could not upgrade state database tables")?;
+            }
+        }
+        transaction
+            .execute(
+                "INSERT OR REPLACE INTO meta (key, value) VALUES ('schema_version', ?1)",
+                params![STATE_VERSION.to_string()],
+            )
+            .context("could not write state database version")?;
+        transaction
+            .commit()
+            .context("could not upgrade state database tables")?;
+
+        Ok(Database {
+            connection,
+            version,
+        })
+    }
+
+    /// Opens the existing database at `path` without writing to it.  Its
+    /// tables must already be up to date, since upgrading them would write.
+    pub fn open_read_only(path: &Path) -> Result<Database, Error> {
+        let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .context("could not open state database")?;
+        let (version, schema_version) = read_versions(&connection)?;
+        if schema_version < STATE_VERSION {
+            return Err(format_err!(
+                "state database tables are from version {}; \
+                 run with --migrate-only to upgrade them",
+                schema_version
+            ));
+        }
+        Ok(Database {
+            connection,
+            version,
+        })
+    }
+
+    pub fn load(&self, config: &Config) -> Result<State, Error> {
+        Ok(self
+            .do_load(config)
+            .context("could not read state database")?)
+    }
+
+    fn do_load(&self, config: &Config) -> Result<State, Error> {
+        let mut tasks = serde_json::json!({
+            "tasks": self.load_tasks("queued")?,
+            "dead_tasks": self.load_tasks("dead")?,
+        });
+        migrations::migrate(&mut tasks, self.version, config)?;
+
+        let mut state = State::new();
+        state.tasks = serde_json::from_value(tasks["tasks"].take())?;
+        state.dead_tasks = serde_json::from_value(tasks["dead_tasks"].take())?;
+
+        let mut statement = self
+            .connection
+            .prepare("SELECT kind, repo, last_time FROM cursors")?;
+        let rows = statement.query_map(NO_PARAMS, |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?;
+        for row in rows {
+            let (kind, repo, last_time) = row?;
+            match kind.as_str() {
+                "wg" => state.wg_repos.entry(repo).or_default().last_time = last_time,
+                "wg-pull-requests" => {
+                    state
+                        .wg_repos
+                        .entry(repo)
+                        .or_default()
+                        .last_pull_request_time = last_time
+                }
+                "wg-discussions" => {
+                    state.wg_repos.entry(repo).or_default().last_discussion_time = last_time
+                }
+                "decisions" => state.decisions_repos.entry(repo).or_default().last_time = last_time,
+                _ => return Err(format_err!("unknown cursor kind {}", kind)),
+            }
+        }
+
+        let mut statement = self
+            .connection
+            .prepare("SELECT repo, url, resolutions, handled_at FROM handled_comments")?;
+        let rows = statement.query_map(NO_PARAMS, |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?;
+        for row in rows {
+            let (repo, url, resolutions, handled_at) = row?;
+            let repo_state = state.wg_repos.entry(repo).or_default();
+            if let Some(resolutions) = resolutions {
+                repo_state
+                    .handled_resolutions
+                    .insert(url.clone(), serde_json::from_str(&resolutions)?);
+            }
+            repo_state.handled_comments.insert(url, handled_at);
+        }
+
+        let mut statement = self.connection.prepare(
+            "SELECT wg_repo, kind, issue_number, decisions_repo, id, number FROM tracking_issues",
+        )?;
+        let rows = statement.query_map(NO_PARAMS, |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, i64>(2)?,
+                TrackingIssue {
+                    decisions_repo: row.get(3)?,
+                    id: row.get(4)?,
+                    number: row.get(5)?,
+                },
+            ))
+        })?;
+        for row in rows {
+            let (wg_repo, kind, issue_number, tracking_issue) = row?;
+            state
+                .wg_repos
+                .entry(wg_repo)
+                .or_default()
+                .tracking_issues_mut(kind.parse()?)
+                .insert(issue_number, tracking_issue);
+        }
+
+        let mut statement = self
+            .connection
+            .prepare("SELECT repo, number, handled_at FROM handled_decisions_issues")?;
+        let rows = statement.query_map(NO_PARAMS, |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?;
+        for row in rows {
+            let (repo, number, handled_at) = row?;
+            state
+                .decisions_repos
+                .entry(repo)
+                .or_default()
+                .handled_issues
+                .insert(number, handled_at);
+        }
+
+        Ok(state)
+    }
+
+    fn load_tasks(&self, list: &str) -> Result<Vec<serde_json::Value>, Error> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT task FROM tasks WHERE list = ?1 ORDER BY position")?;
+        let rows = statement.query_map(params![list], |row| row.get::<_, String>(0))?;
+        let mut tasks = Vec::new();
+        for row in rows {
+            tasks.push(serde_json::from_str(&row?)?);
+        }
+        Ok(tasks)
+    }
+
+    /// Writes the tasks and cursors, and anything recorded in
+    /// `state.changes`, which is then cleared.
+    pub fn save(&mut self, state: &mut State) -> Result<(), Error> {
+        self.save_or_replace(state, false)
+    }
+
+    pub fn compact(&self) -> Result<(), Error> {
+        self.connection
+            .execute_batch("VACUUM")
+            .context("could not compact state database")?;
+        Ok(())
+    }
+
+    /// Replaces everything in the database with the state.
+    pub fn replace(&mut self, state: &mut State) -> Result<(), Error> {
+        mark_all_changed(state);
+        self.save_or_replace(state, true)
+    }
+
+    fn save_or_replace(&mut self, state: &mut State, replace: bool) -> Result<(), Error> {
+        state.merge_posted_tasks();
+        self.do_save(state, replace)
+            .context("could not write state database")?;
+        state.changes.clear();
+        self.version = STATE_VERSION;
+        Ok(())
+    }
+
+    fn do_save(&mut self, state: &State, replace: bool) -> Result<(), Error> {
+        let transaction = self.connection.transaction()?;
+
+        if replace {
+            transaction.execute_batch(
+                "DELETE FROM cursors;
+                 DELETE FROM handled_comments;
+                 DELETE FROM tracking_issues;
+                 DELETE FROM handled_decisions_issues;",
+            )?;
+        }
+
+        transaction.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES ('version', ?1)",
+            params![STATE_VERSION.to_string()],
+        )?;
+
+        // The queue is rewritten in full.  Unlike the handled items it doesn't
+        // grow without bound, since tasks leave it once they're done, and a
+        // task taken from or added to the front moves every other task's
+        // position, so most of its rows would need writing anyway.
+        transaction.execute("DELETE FROM tasks", NO_PARAMS)?;
+        {
+            let mut insert = transaction
+                .prepare("INSERT INTO tasks (list, position, task) VALUES (?1, ?2, ?3)")?;
+            let lists: [(&str, Vec<&QueuedTask>); 2] = [
+                ("queued", state.tasks.iter().collect()),
+                ("dead", state.dead_tasks.iter().collect()),
+            ];
+            for (list, tasks) in &lists {
+                for (position, task) in tasks.iter().enumerate() {
+                    insert.execute(params![list, position as i64, serde_json::to_string(task)?])?;
+                }
+            }
+
+            let mut insert = transaction.prepare(
+                "INSERT OR REPLACE INTO cursors (kind, repo, last_time) VALUES (?1, ?2, ?3)",
+            )?;
+            for (repo, repo_state) in &state.wg_repos {
+                insert.execute(params!["wg", repo, repo_state.last_time])?;
+                insert.execute(params![
+                    "wg-pull-requests",
+                    repo,
+                    repo_state.last_pull_request_time
+                ])?;
+                insert.execute(params![
+                    "wg-discussions",
+                    repo,
+                    repo_state.last_discussion_time
+                ])?;
+            }
+            for (repo, repo_state) in &state.decisions_repos {
+                insert.execute(params!["decisions", repo, repo_state.last_time])?;
+            }
+        }
+
+        for change in &state.changes {
+            match change {
+                Change::HandledComment(repo, url) => {
+                    let repo_state = &state.wg_repos[repo];
+                    let handled_at = match repo_state.handled_comments.get(url) {
+                        Some(handled_at) => handled_at,
+                        None => {
+                            transaction.execute(
+                                "DELETE FROM handled_comments WHERE repo = ?1 AND url = ?2",
+                                params![repo, url],
+                            )?;
+                            continue;
+                        }
+                    };
+                    let resolutions = match repo_state.handled_resolutions.get(url) {
+                        Some(resolutions) => Some(serde_json::to_string(resolutions)?),
+                        None => None,
+                    };
+                    transaction.execute(
+                        "INSERT OR REPLACE INTO handled_comments
+                         (repo, url, resolutions, handled_at)
+                         VALUES (?1, ?2, ?3, ?4)",
+                        params![repo, url, resolutions, handled_at],
+                    )?;
+                }
+                Change::TrackingIssue(wg_repo, kind, issue_number) => {
+                    let tracking_issue =
+                        &state.wg_repos[wg_repo].tracking_issues(*kind)[issue_number];
+                    transaction.execute(
+                        "INSERT OR REPLACE INTO tracking_issues
+                         (wg_repo, kind, issue_number, decisions_repo, id, number)
+                         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                        params![
+                            wg_repo,
+                            kind.as_str(),
+                            issue_number,
+                            tracking_issue.decisions_repo,
+                            tracking_issue.id,
+                            tracking_issue.number
+                        ],
+                    )?;
+                }
+                Change::HandledDecisionsIssue(repo, number) => {
+                    match state.decisions_repos[repo].handled_issues.get(number) {
+                        Some(handled_at) => transaction.execute(
+                            "INSERT OR REPLACE INTO handled_decisions_issues
+                             (repo, number, handled_at)
+                             VALUES (?1, ?2, ?3)",
+                            params![repo, number, handled_at],
+                        )?,
+                        None => transaction.execute(
+                            "DELETE FROM handled_decisions_issues WHERE repo = ?1 AND number = ?2",
+                            params![repo, number],
+                        )?,
+                    };
+                }
+            }
+        }
+
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+/// Records everything in the state as changed, so that the next save writes
+/// all of it.
+pub fn mark_all_changed(state: &mut State) {
+    let mut changes = Vec::new();
+    for (repo, repo_state) in &state.wg_repos {
+        for url in repo_state.handled_comments.keys() {
+            changes.push(Change::HandledComment(repo.clone(), url.clone()));
+        }
+        for &kind in IssueKind::ALL {
+            for issue_number in repo_state.tracking_issues(kind).keys() {
+                changes.push(Change::TrackingIssue(repo.clone(), kind, *issue_number));
+            }
+        }
+    }
+    for (repo, repo_state) in &state.decisions_repos {
+        for number in repo_state.handled_issues.keys() {
+            changes.push(Change::HandledDecisionsIssue(repo.clone(), *number));
+        }
+    }
+    state.changes = changes;
+}
+
+/// Returns the version of the tasks in the database and the version of its
+/// tables.  The tables are upgraded as soon as the database is opened, but the
+/// tasks aren't written back in the current version until the next save, so
+/// the two versions are recorded separately.
+fn read_versions(connection: &Connection) -> Result<(u32, u32), Error> {
+    let version = read_version(connection, "version")?;
+    let schema_version = read_version(connection, "schema_version")?;
+    let (version, schema_version) = match (version, schema_version) {
+        (None, _) => (STATE_VERSION, STATE_VERSION),
+        (Some(version), None) => (version, version),
+        (Some(version), Some(schema_version)) => (version, schema_version),
+    };
+    if schema_version > STATE_VERSION {
+        return Err(format_err!(
+            "unknown state database version number {}",
+            schema_version
+        ));
+    }
+    Ok((version, schema_version))
+}
+
+fn read_version(connection: &Connection, key: &str) -> Result<Option<u32>, Error> {
+    let version = connection
+        .query_row(
+            "SELECT value FROM meta WHERE key = ?1",
+            params![key],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()
+        .context("could not read state database version")?;
+    match version {
+        Some(version) => Ok(Some(
+            version
+                .parse::<u32>()
+                .context("could not parse state database version")?,
+        )),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{StateBackend, StateStore, VersionedState};
+    use crate::tests::StateDirectory;
+    use std::fs;
+    use std::path::PathBuf;
+
+    const CONFIG: &str = r#"
+        github_key = ""
+        bugzilla_key = ""
+        wg_repo_owner = "w3c"
+        wg_repo_name = "csswg-drafts"
+        decisions_repo_owner = "mozilla"
+        decisions_repo_name = "wg-decisions"
+        state_directory = ""
+        start_date = "2019-01-01"
+    "#;
+
+    /// The tables as they were in version 4, when the database was added.
+    const V4_TABLES: &str = "
+        CREATE TABLE meta (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+        CREATE TABLE tasks (
+            list TEXT NOT NULL,
+            position INTEGER NOT NULL,
+            task TEXT NOT NULL,
+            PRIMARY KEY (list, position)
+        );
+        CREATE TABLE cursors (
+            kind TEXT NOT NULL,
+            repo TEXT NOT NULL,
+            last_time TEXT NOT NULL,
+            PRIMARY KEY (kind, repo)
+        );
+        CREATE TABLE handled_comments (
+            repo TEXT NOT NULL,
+            url TEXT NOT NULL,
+            resolutions TEXT,
+            PRIMARY KEY (repo, url)
+        );
+        CREATE TABLE tracking_issues (
+            wg_repo TEXT NOT NULL,
+            issue_number INTEGER NOT NULL,
+            decisions_repo TEXT NOT NULL,
+            id TEXT NOT NULL,
+            number INTEGER NOT NULL,
+            PRIMARY KEY (wg_repo, issue_number)
+        );
+        CREATE TABLE handled_decisions_issues (
+            repo TEXT NOT NULL,
+            number INTEGER NOT NULL,
+            PRIMARY KEY (repo, number)
+        );
+    ";
+
+    /// The changes to `V4_TABLES` in version 5, which are also the tables in
+    /// version 6.
+    const V5_TABLES: &str = "
+        ALTER TABLE handled_comments ADD COLUMN handled_at TEXT NOT NULL DEFAULT '';
+        ALTER TABLE handled_decisions_issues ADD COLUMN handled_at TEXT NOT NULL DEFAULT '';
+    ";
+
+    fn config() -> Config {
+        Config::from_toml(CONFIG).unwrap()
+    }
+
+    /// Creates a database at `version` in the state directory by running
+    /// `sql`, then opens it, upgrading its tables, and loads it.
+    fn upgrade(directory: &StateDirectory, version: u32, sql: &str) -> State {
+        let path = directory.0.join("state.db");
+        let connection = Connection::open(&path).unwrap();
+        connection.execute_batch(sql).unwrap();
+        connection
+            .execute(
+                "INSERT INTO meta (key, value) VALUES ('version', ?1), ('schema_version', ?1)",
+                params![version.to_string()],
+            )
+            .unwrap();
+        drop(connection);
+
+        let database = Database::open(&path).unwrap();
+        assert_eq!(
+            read_version(&database.connection, "schema_version").unwrap(),
+            Some(STATE_VERSION)
+        );
+        database.load(&config()).unwrap()
+    }
+
+    fn fixture_path(version: u32) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("src/state/fixtures")
+            .join(format!("v{}", version))
+    }
+
+    fn to_value(state: &State) -> serde_json::Value {
+        serde_json::to_value(state).unwrap()
+    }
+
+    #[test]
+    fn v4_handled_items_get_cursor_times() {
+        let directory = StateDirectory::new("sqlite-v4");
+        let sql = format!(
+            "{}
+            INSERT INTO cursors VALUES ('wg', 'w3c/csswg-drafts', '2019-06-01T00:00:00Z');
+            INSERT INTO handled_comments VALUES
+                ('w3c/csswg-drafts', 'https://github.com/w3c/csswg-drafts/issues/1#issuecomment-1',
+                 NULL);
+            INSERT INTO handled_decisions_issues VALUES ('mozilla/wg-decisions', 12);",
+            V4_TABLES
+        );
+        let state = upgrade(&directory, 4, &sql);
+
+        let wg_repo = &state.wg_repos["w3c/csswg-drafts"];
+        assert_eq!(
+            wg_repo.handled_comments["https://github.com/w3c/csswg-drafts/issues/1#issuecomment-1"],
+            "2019-06-01T00:00:00Z"
+        );
+        // There is no cursor for the decisions repository, so there is no time
+        // to give the issue.
+        assert_eq!(
+            state.decisions_repos["mozilla/wg-decisions"].handled_issues[&12],
+            ""
+        );
+    }
+
+    #[test]
+    fn v5_pull_requests_and_discussions_start_at_issue_cursor() {
+        let directory = StateDirectory::new("sqlite-v5");
+        let sql = format!(
+            "{}{}
+            INSERT INTO cursors VALUES ('wg', 'w3c/csswg-drafts', '2019-06-01T00:00:00Z');
+            INSERT INTO cursors VALUES ('decisions', 'mozilla/wg-decisions', '2019-05-01T00:00:00Z');",
+            V4_TABLES, V5_TABLES
+        );
+        let state = upgrade(&directory, 5, &sql);
+
+        let wg_repo = &state.wg_repos["w3c/csswg-drafts"];
+        assert_eq!(wg_repo.last_time, "2019-06-01T00:00:00Z");
+        assert_eq!(wg_repo.last_pull_request_time, "2019-06-01T00:00:00Z");
+        assert_eq!(wg_repo.last_discussion_time, "2019-06-01T00:00:00Z");
+        assert!(!state.wg_repos.contains_key("mozilla/wg-decisions"));
+    }
+
+    #[test]
+    fn v6_tracking_issue_kinds_are_guessed_from_handled_comments() {
+        let directory = StateDirectory::new("sqlite-v6");
+        let sql = format!(
+            "{}{}
+            INSERT INTO handled_comments VALUES
+                ('w3c/csswg-drafts', 'https://github.com/w3c/csswg-drafts/pull/2#issuecomment-1',
+                 NULL, ''),
+                ('w3c/csswg-drafts', 'https://github.com/w3c/csswg-drafts/discussions/3#discussioncomment-1',
+                 NULL, ''),
+                ('w3c/csswg-drafts', 'https://github.com/w3c/csswg-drafts/pull/40#issuecomment-1',
+                 NULL, '');
+            INSERT INTO tracking_issues VALUES
+                ('w3c/csswg-drafts', 1, 'mozilla/wg-decisions', 'I1', 11),
+                ('w3c/csswg-drafts', 2, 'mozilla/wg-decisions', 'I2', 12),
+                ('w3c/csswg-drafts', 3, 'mozilla/wg-decisions', 'I3', 13),
+                ('w3c/csswg-drafts', 4, 'mozilla/wg-decisions', 'I4', 14);",
+            V4_TABLES, V5_TABLES
+        );
+        let state = upgrade(&directory, 6, &sql);
+
+        let wg_repo = &state.wg_repos["w3c/csswg-drafts"];
+        let numbers = |kind| {
+            let mut numbers: Vec<i64> = wg_repo.tracking_issues(kind).keys().cloned().collect();
+            numbers.sort();
+            numbers
+        };
+        // Issue 4 has no handled comments of its own; the ones for #40 must
+        // not be mistaken for them.
+        assert_eq!(numbers(IssueKind::Issue), vec![1, 4]);
+        assert_eq!(numbers(IssueKind::PullRequest), vec![2]);
+        assert_eq!(numbers(IssueKind::Discussion), vec![3]);
+        assert_eq!(
+            wg_repo.tracking_issues(IssueKind::Discussion)[&3].number,
+            13
+        );
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let directory = StateDirectory::new("sqlite-round-trip");
+        let path = directory.0.join("state.db");
+        let config = config();
+        let mut state = VersionedState::from_path(&fixture_path(STATE_VERSION), &config).unwrap();
+        let expected = to_value(&state);
+
+        let mut database = Database::open(&path).unwrap();
+        mark_all_changed(&mut state);
+        database.save(&mut state).unwrap();
+        assert!(state.changes.is_empty());
+        drop(database);
+
+        let database = Database::open_read_only(&path).unwrap();
+        assert_eq!(to_value(&database.load(&config).unwrap()), expected);
+    }
+
+    #[test]
+    fn json_state_file_is_imported_once() {
+        let directory = StateDirectory::new("sqlite-import");
+        let config = config();
+        fs::copy(fixture_path(1), directory.0.join("state")).unwrap();
+        let mut expected = VersionedState::from_path(&fixture_path(1), &config).unwrap();
+        expected.merge_posted_tasks();
+
+        let state_directory = directory.0.to_str().unwrap();
+        let mut store = StateStore::new(state_directory, StateBackend::Sqlite);
+        let mut state = store.load(&config, false).unwrap();
+        store.save(&mut state).unwrap();
+        assert!(!directory.0.join("state").exists());
+        assert!(directory.0.join("state.imported").exists());
+
+        // A JSON state file is ignored once there is a database.
+        fs::copy(fixture_path(STATE_VERSION), directory.0.join("state")).unwrap();
+        let mut store = StateStore::new(state_directory, StateBackend::Sqlite);
+        let mut state = store.load(&config, false).unwrap();
+        assert_eq!(to_value(&state), to_value(&expected));
+        store.save(&mut state).unwrap();
+        assert!(directory.0.join("state").exists());
+    }
+}
@@ -0,0 +1,296 @@
+//! A local HTTP server that stands in for GitHub and Bugzilla, so that runs
+//! can be tested without the network.
+//!
+//! GraphQL requests to `/graphql` are answered from recordings, each of which
+//! gives the response for an operation.  A recording can also give some of
+//! the operation's variables, in which case it is only used for requests
+//! whose variables have those values; the first recording that matches a
+//! request is used.  Requests without a matching recording get a 404.  Tests
+//! can also make the next few GraphQL requests fail with a given status.
+//! GraphQL responses carry GitHub's rate limit headers, and each request
+//! uses one point of the rate limit.
+//!
+//! Bugs filed with `/rest/bug` are given increasing IDs, and repo configs are
+//! served from `/{owner}/{name}/master/config.toml`.  Every request is kept
+//! so that tests can check what was sent.
+
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tiny_http::{Header, Request, Response, Server};
+
+/// The first ID given to a filed bug.
+pub const FIRST_BUG_ID: i64 = 1000;
+
+/// A recorded response to a GraphQL operation.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Recording {
+    pub operation: String,
+    /// The variables a request must have for this recording to be used.
+    /// Any variables not listed can have any value.
+    #[serde(default)]
+    pub variables: Option<Value>,
+    pub response: Value,
+}
+
+impl Recording {
+    pub fn new(operation: &str, variables: Option<Value>, response: Value) -> Recording {
+        Recording {
+            operation: operation.to_string(),
+            variables,
+            response,
+        }
+    }
+
+    fn matches(&self, operation: &str, variables: &Value) -> bool {
+        if self.operation != operation {
+            return false;
+        }
+        match &self.variables {
+            Some(Value::Object(wanted)) => wanted
+                .iter()
+                .all(|(name, value)| variables.get(name).unwrap_or(&Value::Null) == value),
+            Some(_) => false,
+            None => true,
+        }
+    }
+}
+
+/// Loads recordings from a JSON file in `src/tests/fixtures`.
+pub fn load_recordings(name: &str) -> Vec<Recording> {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("src/tests/fixtures")
+        .join(name);
+    let json = fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("could not read {}: {}", path.display(), e));
+    serde_json::from_str(&json)
+        .unwrap_or_else(|e| panic!("could not parse {}: {}", path.display(), e))
+}
+
+/// A request the server received.
+#[derive(Clone, Debug)]
+pub struct ReceivedRequest {
+    pub path: String,
+    /// The GraphQL operation name, for GraphQL requests.
+    pub operation: Option<String>,
+    /// The GraphQL variables for GraphQL requests, or the request body for
+    /// Bugzilla requests.
+    pub body: Value,
+    /// Whether there was a recording or other response for the request.
+    pub matched: bool,
+}
+
+#[derive(Default)]
+struct Shared {
+    recordings: Vec<Recording>,
+    repo_configs: HashMap<String, String>,
+    requests: Vec<ReceivedRequest>,
+    next_bug_id: i64,
+    /// The number of GraphQL requests still to fail, and their status.
+    failures: usize,
+    failure_status: u16,
+    /// The rate limit points remaining, and when the rate limit resets, in
+    /// seconds since the Unix epoch.
+    rate_limit_remaining: i64,
+    rate_limit_reset_at: i64,
+}
+
+pub struct FakeServer {
+    url: String,
+    shared: Arc<Mutex<Shared>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl FakeServer {
+    pub fn start(recordings: Vec<Recording>) -> FakeServer {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", server.server_addr());
+        let shared = Arc::new(Mutex::new(Shared {
+            recordings,
+            next_bug_id: FIRST_BUG_ID,
+            rate_limit_remaining: 5000,
+            rate_limit_reset_at: Utc::now().timestamp() + 3600,
+            ..Default::default()
+        }));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let thread_shared = shared.clone();
+        let thread_stop = stop.clone();
+        thread::spawn(move || {
+            while !thread_stop.load(Ordering::SeqCst) {
+                if let Ok(Some(request)) = server.recv_timeout(Duration::from_millis(100)) {
+                    handle_request(request, &thread_shared);
+                }
+            }
+        });
+
+        FakeServer { url, shared, stop }
+    }
+
+    /// Returns the base URL of the server, e.g. `http://127.0.0.1:1234`.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn add_recording(&self, recording: Recording) {
+        self.shared.lock().unwrap().recordings.push(recording);
+    }
+
+    /// Makes the next `count` GraphQL requests fail with `status`, whatever
+    /// their recordings say.
+    pub fn fail_requests(&self, count: usize, status: u16) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.failures = count;
+        shared.failure_status = status;
+    }
+
+    /// Sets the rate limit points remaining, and how many seconds from now
+    /// the rate limit resets.
+    pub fn set_rate_limit(&self, remaining: i64, reset_in_seconds: i64) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.rate_limit_remaining = remaining;
+        shared.rate_limit_reset_at = Utc::now().timestamp() + reset_in_seconds;
+    }
+
+    /// Sets the repo config served for a decisions repository.  Repositories
+    /// without one get an empty repo config.
+    pub fn set_repo_config(&self, repo: &str, toml: &str) {
+        self.shared
+            .lock()
+            .unwrap()
+            .repo_configs
+            .insert(repo.to_string(), toml.to_string());
+    }
+
+    pub fn requests(&self) -> Vec<ReceivedRequest> {
+        self.shared.lock().unwrap().requests.clone()
+    }
+
+    /// Returns the variables of each request for a GraphQL operation.
+    pub fn operations(&self, operation: &str) -> Vec<Value> {
+        self.requests()
+            .into_iter()
+            .filter(|r| r.operation.as_deref() == Some(operation))
+            .map(|r| r.body)
+            .collect()
+    }
+
+    /// Returns the bodies of the requests to file bugs.
+    pub fn filed_bugs(&self) -> Vec<Value> {
+        self.requests()
+            .into_iter()
+            .filter(|r| r.path == "/rest/bug")
+            .map(|r| r.body)
+            .collect()
+    }
+
+    /// Returns the requests that had no recording.
+    pub fn unmatched(&self) -> Vec<ReceivedRequest> {
+        self.requests().into_iter().filter(|r| !r.matched).collect()
+    }
+
+    pub fn clear_requests(&self) {
+        self.shared.lock().unwrap().requests.clear();
+    }
+}
+
+impl Drop for FakeServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+fn handle_request(mut request: Request, shared: &Mutex<Shared>) {
+    let path = request.url().to_string();
+    let mut body = String::new();
+    let _ = request.as_reader().read_to_string(&mut body);
+    let body = serde_json::from_str(&body).unwrap_or(Value::Null);
+
+    let mut shared = shared.lock().unwrap();
+    let (operation, body, response, headers) = if path == "/graphql" {
+        let operation = body["operationName"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        let variables = body["variables"].clone();
+        shared.rate_limit_remaining = (shared.rate_limit_remaining - 1).max(0);
+        let rate_limit_headers = [
+            Header::from_bytes(
+                "X-RateLimit-Remaining",
+                shared.rate_limit_remaining.to_string(),
+            )
+            .unwrap(),
+            Header::from_bytes("X-RateLimit-Reset", shared.rate_limit_reset_at.to_string())
+                .unwrap(),
+        ];
+        if shared.failures > 0 {
+            shared.failures -= 1;
+            let status = shared.failure_status;
+            shared.requests.push(ReceivedRequest {
+                path,
+                operation: Some(operation),
+                body: variables,
+                matched: true,
+            });
+            let mut response = Response::from_string("failed").with_status_code(status);
+            for header in rate_limit_headers.iter().cloned() {
+                response.add_header(header);
+            }
+            let _ = request.respond(response);
+            return;
+        }
+        let response = shared
+            .recordings
+            .iter()
+            .find(|r| r.matches(&operation, &variables))
+            .map(|r| r.response.clone());
+        (
+            Some(operation),
+            variables,
+            response,
+            rate_limit_headers.to_vec(),
+        )
+    } else if path == "/rest/bug" {
+        let id = shared.next_bug_id;
+        shared.next_bug_id += 1;
+        (None, body, Some(json!({ "id": id })), Vec::new())
+    } else if let Some(repo) = path
+        .strip_prefix('/')
+        .and_then(|p| p.strip_suffix("/master/config.toml"))
+    {
+        let toml = shared.repo_configs.get(repo).cloned().unwrap_or_default();
+        shared.requests.push(ReceivedRequest {
+            path,
+            operation: None,
+            body,
+            matched: true,
+        });
+        let _ = request.respond(Response::from_string(toml));
+        return;
+    } else {
+        (None, body, None, Vec::new())
+    };
+
+    shared.requests.push(ReceivedRequest {
+        path,
+        operation,
+        body,
+        matched: response.is_some(),
+    });
+
+    let mut response = match response {
+        Some(response) => Response::from_string(response.to_string())
+            .with_header("Content-Type: application/json".parse::<Header>().unwrap()),
+        None => Response::from_string("no recording for this request").with_status_code(404),
+    };
+    for header in headers {
+        response.add_header(header);
+    }
+    let _ = request.respond(response);
+}
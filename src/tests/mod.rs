@@ -0,0 +1,719 @@
+//! End-to-end tests that run the tracker against a fake GitHub and Bugzilla.
+
+mod fake_server;
+
+use crate::config::Config;
+use crate::query;
+use crate::state::StateStore;
+use crate::tracker::Tracker;
+use fake_server::{load_recordings, FakeServer, Recording, FIRST_BUG_ID};
+use fs2::FileExt;
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A state directory that is removed when the test finishes.
+pub struct StateDirectory(pub PathBuf);
+
+impl StateDirectory {
+    pub fn new(name: &str) -> StateDirectory {
+        let path = std::env::temp_dir().join(format!("wg-tracker-test-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        StateDirectory(path)
+    }
+}
+
+impl Drop for StateDirectory {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn config(server: &FakeServer, state_directory: &Path) -> Config {
+    Config::from_toml(&format!(
+        r#"
+        github_key = "github-key"
+        bugzilla_key = "bugzilla-key"
+        wg_repo_owner = "w3c"
+        wg_repo_name = "csswg-drafts"
+        decisions_repo_owner = "mozilla"
+        decisions_repo_name = "wg-decisions"
+        state_directory = "{}"
+        start_date = "2019-01-01"
+        github_endpoint = "{url}/graphql"
+        bugzilla_url = "{url}"
+        raw_content_url = "{url}"
+        "#,
+        state_directory.display(),
+        url = server.url(),
+    ))
+    .unwrap()
+}
+
+/// Does a single run of the tracker, as `wg-tracker CONFIG run` would, and
+/// checks that every request it made had a recording.
+fn run(server: &FakeServer, state_directory: &StateDirectory) {
+    Tracker::new(config(server, &state_directory.0))
+        .run()
+        .unwrap();
+    assert_eq!(
+        server.unmatched().len(),
+        0,
+        "requests without recordings: {:#?}",
+        server.unmatched()
+    );
+}
+
+#[test]
+fn new_resolution_files_tracking_issue() {
+    let server = FakeServer::start(load_recordings("resolved_comment.json"));
+    server.set_repo_config("mozilla/wg-decisions", "[labels]\nprefixes = [\"css-\"]\n");
+    let state_directory = StateDirectory::new("new-resolution");
+
+    run(&server, &state_directory);
+
+    let created_labels = server.operations("CreateLabel");
+    assert_eq!(created_labels.len(), 1);
+    assert_eq!(created_labels[0]["name"], "[spec] css-grid-2");
+    assert_eq!(created_labels[0]["color"], "fbca04");
+
+    let searches = server.operations("SearchIssues");
+    assert_eq!(searches.len(), 1);
+    assert!(searches[0]["query"]
+        .as_str()
+        .unwrap()
+        .contains("https://github.com/w3c/csswg-drafts/issues/1234#issuecomment-102"));
+
+    let created_issues = server.operations("CreateIssue");
+    assert_eq!(created_issues.len(), 1);
+    let issue = &created_issues[0];
+    assert_eq!(
+        issue["title"],
+        "[css-grid] Clarify min-content contribution"
+    );
+    assert_eq!(issue["labels"], json!(["MDU6TGFiZWwy"]));
+    let body = issue["body"].as_str().unwrap();
+    assert!(body.starts_with(
+        "A resolution was made for \
+         [csswg-drafts/#1234](https://github.com/w3c/csswg-drafts/issues/1234)."
+    ));
+    assert!(body.contains(
+        "* RESOLVED: Use the min\\-content size of the item when it spans flexible tracks\n"
+    ));
+    assert!(body.contains(
+        "[Discussion.](https://github.com/w3c/csswg-drafts/issues/1234#issuecomment-102)"
+    ));
+    assert!(server.filed_bugs().is_empty());
+
+    // The next run sees the same comment again, since the issue was updated
+    // at the time we now query from, but the resolution is already tracked.
+    server.clear_requests();
+    run(&server, &state_directory);
+
+    let updated_issues = server.operations("UpdatedIssues");
+    assert_eq!(updated_issues[0]["since"], "2019-03-01T17:00:00Z");
+    assert_eq!(server.operations("IssueComments").len(), 1);
+    assert!(server.operations("CreateIssue").is_empty());
+    assert!(server.operations("SearchIssues").is_empty());
+}
+
+#[test]
+fn bug_label_files_bug_and_closes_decisions_issue() {
+    let server = FakeServer::start(load_recordings("bug_label.json"));
+    server.set_repo_config(
+        "mozilla/wg-decisions",
+        "[components]\ncss-grid = \"Core :: Layout: Grid\"\n",
+    );
+    let state_directory = StateDirectory::new("bug-label");
+
+    run(&server, &state_directory);
+
+    let bugs = server.filed_bugs();
+    assert_eq!(bugs.len(), 1);
+    let bug = &bugs[0];
+    assert_eq!(bug["api_key"], "bugzilla-key");
+    assert_eq!(bug["product"], "Core");
+    assert_eq!(bug["component"], "Layout: Grid");
+    assert_eq!(
+        bug["summary"],
+        "[css-grid] Clarify min-content contribution"
+    );
+    assert!(!bug["description"]
+        .as_str()
+        .unwrap()
+        .contains("To file a bug automatically"));
+    assert_eq!(
+        bug["see_also"],
+        json!([
+            "https://github.com/w3c/csswg-drafts/issues/1234",
+            "https://github.com/w3c/csswg-drafts/issues/1234#issuecomment-102",
+            format!("{}/mozilla/wg-decisions/issues/42", server.url()),
+        ])
+    );
+
+    let comments = server.operations("AddIssueComment");
+    assert_eq!(comments.len(), 1);
+    assert_eq!(
+        comments[0]["body"],
+        format!("{}/show_bug.cgi?id={}", server.url(), FIRST_BUG_ID)
+    );
+
+    let removed_labels = server.operations("RemoveLabels");
+    assert_eq!(removed_labels.len(), 1);
+    assert_eq!(removed_labels[0]["labels"], json!(["MDU6TGFiZWwx"]));
+    assert_eq!(server.operations("CloseIssue").len(), 1);
+
+    // The issue still has the label as far as the next run can tell, but it
+    // has already been handled.
+    server.clear_requests();
+    run(&server, &state_directory);
+
+    assert!(server.filed_bugs().is_empty());
+    assert!(server.operations("CloseIssue").is_empty());
+}
+
+#[test]
+fn pull_request_and_discussion_resolutions_link_to_them() {
+    let server = FakeServer::start(load_recordings("pull_request_and_discussion.json"));
+    let state_directory = StateDirectory::new("pull-request-and-discussion");
+
+    run(&server, &state_directory);
+
+    // Pull requests are fetched newest first, so the one updated before the
+    // start date means there's no need for the next page.
+    let pull_requests = server
+        .operations("UpdatedPullRequests")
+        .into_iter()
+        .filter(|v| v["repo_owner"] == "w3c")
+        .collect::<Vec<_>>();
+    assert_eq!(pull_requests.len(), 1);
+    assert_eq!(server.operations("PullRequestComments")[0]["number"], 20);
+
+    let mut bodies = server
+        .operations("CreateIssue")
+        .into_iter()
+        .map(|v| v["body"].as_str().unwrap().to_string())
+        .collect::<Vec<_>>();
+    bodies.sort();
+    assert_eq!(bodies.len(), 2);
+    assert!(bodies[0].starts_with(
+        "A resolution was made for \
+         [csswg-drafts/#20](https://github.com/w3c/csswg-drafts/pull/20)."
+    ));
+    assert!(bodies[0]
+        .contains("[Discussion.](https://github.com/w3c/csswg-drafts/pull/20#issuecomment-201)"));
+    assert!(bodies[1].starts_with(
+        "A resolution was made for \
+         [csswg-drafts/#30](https://github.com/w3c/csswg-drafts/discussions/30)."
+    ));
+    assert!(bodies[1].contains(
+        "[Discussion.](https://github.com/w3c/csswg-drafts/discussions/30#discussioncomment-301)"
+    ));
+
+    // The next run sees the same comments again, since the pull request and
+    // discussion were updated at the times we now query from, but the
+    // resolutions are already tracked.
+    server.clear_requests();
+    run(&server, &state_directory);
+
+    assert_eq!(server.operations("PullRequestComments").len(), 1);
+    assert_eq!(server.operations("DiscussionComments").len(), 1);
+    assert!(server.operations("CreateIssue").is_empty());
+}
+
+/// Adds recordings for WG repositories without any pull requests or
+/// discussions.
+fn add_no_pull_requests_or_discussions(server: &FakeServer) {
+    for (operation, connection) in &[
+        ("UpdatedPullRequests", "pullRequests"),
+        ("UpdatedDiscussions", "discussions"),
+    ] {
+        server.add_recording(Recording::new(
+            operation,
+            None,
+            json!({
+                "data": {
+                    "repository": {
+                        *connection: {
+                            "pageInfo": { "hasNextPage": false, "endCursor": null },
+                            "edges": [],
+                        },
+                    },
+                },
+            }),
+        ));
+    }
+}
+
+/// Returns the time a generated WG issue was last updated, a minute apart
+/// for each issue.
+fn updated_at(number: i64) -> String {
+    format!("2019-02-01T{:02}:{:02}:00Z", number / 60, number % 60)
+}
+
+/// Returns a page of generated WG issues, numbered from `first` to `last`
+/// out of `total`.
+fn updated_issues_page(first: i64, last: i64, total: i64) -> Value {
+    let edges = (first..=last)
+        .map(|number| {
+            json!({
+                "node": {
+                    "id": format!("issue-{}", number),
+                    "number": number,
+                    "title": format!("Issue {}", number),
+                    "updatedAt": updated_at(number),
+                    "closed": false,
+                    "labels": { "edges": [] },
+                },
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({
+        "data": {
+            "repository": {
+                "issues": {
+                    "pageInfo": {
+                        "hasNextPage": last < total,
+                        "endCursor": format!("cursor-{}", last),
+                    },
+                    "edges": edges,
+                },
+            },
+        },
+    })
+}
+
+#[test]
+fn updated_issues_are_paginated() {
+    let server = FakeServer::start(Vec::new());
+    for &(after, first, last) in &[
+        (None, 1, 100),
+        (Some("cursor-100"), 101, 200),
+        (Some("cursor-200"), 201, 250),
+    ] {
+        server.add_recording(Recording::new(
+            "UpdatedIssues",
+            Some(json!({ "repo_owner": "w3c", "after": after })),
+            updated_issues_page(first, last, 250),
+        ));
+    }
+    server.add_recording(Recording::new(
+        "UpdatedIssues",
+        Some(json!({ "repo_owner": "mozilla" })),
+        json!({
+            "data": {
+                "repository": {
+                    "issues": {
+                        "pageInfo": { "hasNextPage": false, "endCursor": null },
+                        "edges": [],
+                    },
+                },
+            },
+        }),
+    ));
+    server.add_recording(Recording::new(
+        "IssueComments",
+        None,
+        json!({
+            "data": {
+                "repository": {
+                    "issue": {
+                        "comments": {
+                            "pageInfo": { "hasPreviousPage": false, "startCursor": null },
+                            "edges": [],
+                        },
+                    },
+                },
+            },
+        }),
+    ));
+    add_no_pull_requests_or_discussions(&server);
+    let state_directory = StateDirectory::new("pagination");
+
+    run(&server, &state_directory);
+
+    let pages = server
+        .operations("UpdatedIssues")
+        .into_iter()
+        .filter(|v| v["repo_owner"] == "w3c")
+        .map(|v| v["after"].clone())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        pages,
+        vec![json!(null), json!("cursor-100"), json!("cursor-200")]
+    );
+    assert!(server
+        .operations("UpdatedIssues")
+        .iter()
+        .all(|v| v["page_size"] == 100));
+
+    let mut numbers = server
+        .operations("IssueComments")
+        .into_iter()
+        .map(|v| v["number"].as_i64().unwrap())
+        .collect::<Vec<_>>();
+    numbers.sort();
+    assert_eq!(numbers, (1..=250).collect::<Vec<_>>());
+
+    // The next run only asks for issues updated since the last one we saw.
+    server.clear_requests();
+    run(&server, &state_directory);
+
+    let updated_issues = server.operations("UpdatedIssues");
+    assert_eq!(updated_issues[0]["since"], json!(updated_at(250)));
+}
+
+/// Returns a comment on a WG issue.
+fn comment(
+    number: i64,
+    id: i64,
+    created_at: &str,
+    last_edited_at: Option<&str>,
+    body_text: &str,
+) -> Value {
+    json!({
+        "node": {
+            "createdAt": created_at,
+            "lastEditedAt": last_edited_at,
+            "url": format!("https://github.com/w3c/csswg-drafts/issues/{}#issuecomment-{}", number, id),
+            "bodyText": body_text,
+        },
+    })
+}
+
+/// Returns a page of comments on a WG issue, oldest first.
+fn issue_comments_page(comments: Vec<Value>, start_cursor: Option<&str>) -> Value {
+    json!({
+        "data": {
+            "repository": {
+                "issue": {
+                    "comments": {
+                        "pageInfo": {
+                            "hasPreviousPage": start_cursor.is_some(),
+                            "startCursor": start_cursor,
+                        },
+                        "edges": comments,
+                    },
+                },
+            },
+        },
+    })
+}
+
+#[test]
+fn issue_comments_fetch_every_page() {
+    let server = FakeServer::start(Vec::new());
+    server.add_recording(Recording::new(
+        "UpdatedIssues",
+        Some(json!({ "repo_owner": "w3c" })),
+        updated_issues_page(1, 1, 1),
+    ));
+    server.add_recording(Recording::new(
+        "UpdatedIssues",
+        Some(json!({ "repo_owner": "mozilla" })),
+        json!({
+            "data": {
+                "repository": {
+                    "issues": {
+                        "pageInfo": { "hasNextPage": false, "endCursor": null },
+                        "edges": [],
+                    },
+                },
+            },
+        }),
+    ));
+    for &(before, start_cursor, first_id, created_at) in &[
+        (
+            None,
+            Some("comments-2"),
+            5,
+            ["2019-01-05T00:00:00Z", "2019-01-06T00:00:00Z"],
+        ),
+        (
+            Some("comments-2"),
+            Some("comments-1"),
+            3,
+            ["2018-12-30T00:00:00Z", "2018-12-31T00:00:00Z"],
+        ),
+        (
+            Some("comments-1"),
+            None,
+            1,
+            ["2018-12-20T00:00:00Z", "2018-12-21T00:00:00Z"],
+        ),
+    ] {
+        let comments = created_at
+            .iter()
+            .enumerate()
+            .map(|(i, t)| comment(1, first_id + i as i64, t, None, ""))
+            .collect();
+        server.add_recording(Recording::new(
+            "IssueComments",
+            Some(json!({ "before": before })),
+            issue_comments_page(comments, start_cursor),
+        ));
+    }
+    add_no_pull_requests_or_discussions(&server);
+    let state_directory = StateDirectory::new("comment-pagination");
+
+    run(&server, &state_directory);
+
+    // Only the newest page has comments from after the start date, but the
+    // older pages are still needed in case their comments were edited.
+    let pages = server
+        .operations("IssueComments")
+        .into_iter()
+        .map(|v| v["before"].clone())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        pages,
+        vec![json!(null), json!("comments-2"), json!("comments-1")]
+    );
+}
+
+#[test]
+fn edited_comment_on_first_page_is_seen() {
+    let mut recordings = load_recordings("resolved_comment.json");
+    recordings.retain(|r| r.operation != "IssueComments");
+    let server = FakeServer::start(recordings);
+    server.set_repo_config("mozilla/wg-decisions", "[labels]\nprefixes = [\"css-\"]\n");
+    let pages = vec![
+        (
+            None,
+            Some("comments-2"),
+            comment(
+                1234,
+                103,
+                "2018-12-03T00:00:00Z",
+                None,
+                "Agenda+ for the F2F.",
+            ),
+        ),
+        (
+            Some("comments-2"),
+            Some("comments-1"),
+            comment(1234, 102, "2018-12-02T00:00:00Z", None, "Sounds good."),
+        ),
+        (
+            Some("comments-1"),
+            None,
+            comment(
+                1234,
+                101,
+                "2018-12-01T00:00:00Z",
+                Some("2019-03-01T17:00:00Z"),
+                "RESOLVED: Use the min-content size of the item when it spans flexible tracks",
+            ),
+        ),
+    ];
+    for (before, start_cursor, comment) in pages {
+        server.add_recording(Recording::new(
+            "IssueComments",
+            Some(json!({ "number": 1234, "before": before })),
+            issue_comments_page(vec![comment], start_cursor),
+        ));
+    }
+    let state_directory = StateDirectory::new("edited-first-page");
+
+    run(&server, &state_directory);
+
+    // The comment on the oldest page was edited to add a resolution after
+    // the start date, though nothing newer was.
+    let created_issues = server.operations("CreateIssue");
+    assert_eq!(created_issues.len(), 1);
+    assert!(created_issues[0]["body"].as_str().unwrap().contains(
+        "[Discussion.](https://github.com/w3c/csswg-drafts/issues/1234#issuecomment-101)"
+    ));
+}
+
+#[test]
+fn read_only_commands_run_while_locked() {
+    let server = FakeServer::start(load_recordings("resolved_comment.json"));
+    server.set_repo_config("mozilla/wg-decisions", "[labels]\nprefixes = [\"css-\"]\n");
+    let state_directory = StateDirectory::new("read-only-locked");
+    run(&server, &state_directory);
+
+    // Another instance, such as a daemon, holds the lock.
+    let lockfile = fs::File::create(state_directory.0.join("lock")).unwrap();
+    lockfile.try_lock_exclusive().unwrap();
+
+    let mut tracker = Tracker::new(config(&server, &state_directory.0));
+    tracker.status().unwrap();
+    tracker.list_queue().unwrap();
+    assert!(Tracker::new(config(&server, &state_directory.0))
+        .gc()
+        .is_err());
+}
+
+#[test]
+fn run_stops_when_rate_limit_resets_too_late() {
+    let server = FakeServer::start(load_recordings("resolved_comment.json"));
+    server.set_repo_config("mozilla/wg-decisions", "[labels]\nprefixes = [\"css-\"]\n");
+    let state_directory = StateDirectory::new("rate-limit-stop");
+
+    // After the first query, fewer than `rate_limit_minimum` points are left,
+    // and they reset later than `rate_limit_max_sleep_seconds` from now.
+    let config = config(&server, &state_directory.0);
+    server.set_rate_limit(
+        config.rate_limit_minimum,
+        config.rate_limit_max_sleep_seconds + 60,
+    );
+    let start = Instant::now();
+    run(&server, &state_directory);
+    assert!(start.elapsed() < Duration::from_secs(10));
+
+    let operations = server
+        .requests()
+        .into_iter()
+        .filter_map(|r| r.operation)
+        .collect::<Vec<_>>();
+    assert_eq!(operations, vec!["UpdatedIssues"]);
+
+    // The tasks it queued are saved for the next run.
+    let state = StateStore::new(&config.state_directory, config.state_backend)
+        .load(&config, true)
+        .unwrap();
+    assert!(!state.is_finished());
+
+    // The next run, in a new process that has yet to see the rate limit,
+    // starts with them once it has been reset.
+    server.set_rate_limit(5000, 3600);
+    server.clear_requests();
+    thread::scope(|scope| {
+        scope.spawn(|| run(&server, &state_directory));
+    });
+    let first = server
+        .requests()
+        .into_iter()
+        .find(|r| r.operation.is_some())
+        .unwrap();
+    assert_eq!(first.operation.as_deref(), Some("IssueComments"));
+    assert_eq!(first.body["number"], 1234);
+    assert_eq!(server.operations("CreateIssue").len(), 1);
+}
+
+/// Returns the names of the snapshots in the state directory, oldest first.
+fn snapshot_names(state_directory: &StateDirectory) -> Vec<String> {
+    let mut names = fs::read_dir(state_directory.0.join("snapshots"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+#[test]
+fn restore_replaces_state_only_with_valid_snapshots() {
+    let server = FakeServer::start(load_recordings("resolved_comment.json"));
+    server.set_repo_config("mozilla/wg-decisions", "[labels]\nprefixes = [\"css-\"]\n");
+    let state_directory = StateDirectory::new("restore");
+    run(&server, &state_directory);
+
+    // The snapshot taken before the run has none of its state.
+    let names = snapshot_names(&state_directory);
+    assert_eq!(names.len(), 1);
+    let initial = names[0].clone();
+
+    let mut dry_run_config = config(&server, &state_directory.0);
+    dry_run_config.dry_run = true;
+    Tracker::new(dry_run_config).restore(&initial).unwrap();
+    assert_eq!(snapshot_names(&state_directory), names);
+
+    let unparsable = "state-29990101T000000.000Z.gz";
+    fs::write(
+        state_directory.0.join("snapshots").join(unparsable),
+        "garbage",
+    )
+    .unwrap();
+    assert!(Tracker::new(config(&server, &state_directory.0))
+        .restore(unparsable)
+        .is_err());
+    assert_eq!(
+        snapshot_names(&state_directory),
+        vec![initial.clone(), unparsable.to_string()]
+    );
+
+    // Neither restore changed the state, so the resolution is still tracked.
+    server.clear_requests();
+    run(&server, &state_directory);
+    assert!(server.operations("SearchIssues").is_empty());
+
+    Tracker::new(config(&server, &state_directory.0))
+        .restore(&initial)
+        .unwrap();
+    server.clear_requests();
+    run(&server, &state_directory);
+    assert_eq!(
+        server.operations("UpdatedIssues")[0]["since"],
+        "2019-01-01T00:00:00Z"
+    );
+    assert_eq!(server.operations("SearchIssues").len(), 1);
+}
+
+#[test]
+fn daemon_keeps_polling_after_a_failed_poll() {
+    let server = Arc::new(FakeServer::start(load_recordings("resolved_comment.json")));
+    server.set_repo_config("mozilla/wg-decisions", "[labels]\nprefixes = [\"css-\"]\n");
+    let state_directory = StateDirectory::new("daemon-failed-poll");
+    let mut config = config(&server, &state_directory.0);
+    config.poll_interval_seconds = 0;
+
+    // The first poll stops at its first query, since GitHub rejects our
+    // credentials, which would end a single run.
+    server.fail_requests(1, 401);
+
+    let mut tracker = Tracker::new(config);
+    let stop = tracker.stop_handle();
+    let thread_server = server.clone();
+    let stopper = thread::spawn(move || {
+        let deadline = Instant::now() + Duration::from_secs(30);
+        while thread_server.operations("CreateIssue").is_empty() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        stop.store(true, Ordering::SeqCst);
+    });
+    tracker.daemon("").unwrap();
+    stopper.join().unwrap();
+
+    assert!(server.operations("UpdatedIssues").len() >= 2);
+    assert_eq!(server.operations("CreateIssue").len(), 1);
+}
+
+#[test]
+fn recorded_run_can_be_replayed() {
+    let server = FakeServer::start(load_recordings("resolved_comment.json"));
+    server.set_repo_config("mozilla/wg-decisions", "[labels]\nprefixes = [\"css-\"]\n");
+    let state_directory = StateDirectory::new("record");
+    run(&server, &state_directory);
+
+    // Record a second run, which starts from the state the first one left.
+    let recording = StateDirectory::new("record-recording");
+    query::record(recording.0.to_str().unwrap()).unwrap();
+    server.clear_requests();
+    run(&server, &state_directory);
+    let recorded_requests = server.requests().len();
+    assert!(recorded_requests > 0);
+    assert!(recording.0.join("state").exists());
+
+    // Replaying makes the same requests, which it can only do if it starts
+    // from the recorded state, and they are all answered by the recording.
+    let replay_directory = StateDirectory::new("record-replay");
+    query::replay(recording.0.to_str().unwrap()).unwrap();
+    server.clear_requests();
+    run(&server, &replay_directory);
+    assert!(server.requests().is_empty());
+    assert_eq!(query::unreplayed(), 0);
+
+    // Only the lockfile is written to the state directory.
+    let files = fs::read_dir(&replay_directory.0)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect::<Vec<_>>();
+    assert_eq!(files, vec!["lock"]);
+}
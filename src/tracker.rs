@@ -1,39 +1,54 @@
-use crate::config::Config;
-use crate::repo_config::RepoConfig;
-use crate::state::VersionedState;
-use crate::util::CLIENT;
-use failure::{Error, ResultExt};
+use crate::client::{BugTracker, GitHubClient};
+use crate::config::{split_repo_id, Config};
+use crate::metrics;
+use crate::query::{self, Bugzilla, GitHub, IssueKind, QueryError};
+use crate::repo_config::{RepoConfig, RepoConfigs};
+use crate::state::{StateStore, VersionedState, STATE_VERSION};
+use crate::util::{print_error, print_info};
+use crate::webhook;
+use chrono::Utc;
+use failure::{format_err, Error, ResultExt};
 use fs2::FileExt;
+use std::cmp;
 use std::fs::File;
-use std::path::{Path, PathBuf};
+use std::mem;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 pub struct Tracker {
     config: Config,
-    repo_config: RepoConfig,
+    repo_configs: RepoConfigs,
     lockfile: Option<File>,
     state: VersionedState,
-
-    statefile_path: PathBuf,
-    statefile_temp_path: PathBuf,
+    /// Set when we have been asked to stop, so that we finish the current
+    /// task and then return.
+    stop: Arc<AtomicBool>,
+    store: StateStore,
+    github: Box<dyn GitHubClient>,
+    bug_tracker: Box<dyn BugTracker>,
+    /// When we last took a snapshot of the state, if we have.
+    last_snapshot: Option<Instant>,
 }
 
 impl Tracker {
     pub fn new(config: Config) -> Tracker {
-        let state_directory_path = Path::new(&config.state_directory);
-
-        let mut statefile_path = state_directory_path.to_path_buf();
-        statefile_path.push("state");
-
-        let mut statefile_temp_path = state_directory_path.to_path_buf();
-        statefile_temp_path.push("state.temp");
-
+        let store = StateStore::new(&config.state_directory, config.state_backend);
+        let github = Box::new(GitHub::new(&config));
+        let bug_tracker = Box::new(Bugzilla::new(&config));
         Tracker {
             config,
-            repo_config: Default::default(),
+            repo_configs: Default::default(),
             lockfile: None,
             state: Default::default(),
-            statefile_path,
-            statefile_temp_path,
+            stop: Default::default(),
+            store,
+            github,
+            bug_tracker,
+            last_snapshot: None,
         }
     }
 
@@ -43,38 +58,432 @@ impl Tracker {
             return Ok(());
         }
 
-        let repo_config_url = format!(
-            "https://raw.githubusercontent.com/{}/{}/master/config.toml",
-            self.config.decisions_repo_owner, self.config.decisions_repo_name
-        );
-        let repo_config_toml = CLIENT
-            .get(&repo_config_url)
-            .send()
-            .context("could not perform network request")?
-            .text()
-            .context("could not read request body")?;
-
-        self.repo_config = RepoConfig::from_str(&repo_config_toml)?;
-
-        self.state = if self.statefile_path.exists() {
-            VersionedState::from_path(&self.statefile_path)?
-        } else {
-            VersionedState::new(&self.config.start_date)
+        self.load_repo_configs()?;
+        self.load_state(!self.saves_state())?;
+
+        self.check_for_updates()?;
+        let result = self.process_queue();
+        if self.saves_state() {
+            metrics::write_textfile(&self.config.state_directory)?;
+        }
+        result
+    }
+
+    /// Runs an HTTP server that accepts GitHub webhook deliveries and
+    /// processes the updates they tell us about straight away, while still
+    /// polling for updates periodically in case any deliveries are missed.
+    /// SIGTERM or SIGINT make us stop once the current task is done.
+    pub fn serve(&mut self) -> Result<(), Error> {
+        let secret = match &self.config.webhook_secret {
+            Some(secret) => secret.clone(),
+            None => return Err(format_err!("config file has no webhook_secret")),
         };
+        self.register_stop_signals()?;
+
+        self.lock_and_load_state()?;
+        self.load_repo_configs()?;
+
+        if let Some(address) = &self.config.metrics_address {
+            metrics::serve(address)?;
+        }
 
-        self.state.check_for_updates();
+        let events = webhook::listen(&self.config.webhook_address, &secret)?;
+        print_info(&format!(
+            "listening for webhooks on {}",
+            self.config.webhook_address
+        ));
 
+        let poll_interval = Duration::from_secs(self.config.poll_interval_seconds);
+        let mut next_poll = Instant::now();
         loop {
-            let result = self.state.iterate(&self.config, &self.repo_config);
-            self.state
-                .save(&self.statefile_path, &self.statefile_temp_path)?;
-            result?;
+            if self.stop.load(Ordering::SeqCst) {
+                print_info("stopping");
+                return Ok(());
+            }
+            let now = Instant::now();
+            if now >= next_poll {
+                self.poll();
+                next_poll = now + poll_interval;
+                continue;
+            }
+            // Wake up at least once a second to see if we've been asked to
+            // stop.
+            match events.recv_timeout(cmp::min(next_poll - now, Duration::from_secs(1))) {
+                Ok(event) => {
+                    self.state.handle_webhook_event(&self.config, event);
+                    if let Err(e) = self.process_queue() {
+                        print_error(&e);
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(format_err!("webhook server stopped"));
+                }
+            }
+        }
+    }
+
+    /// Runs as a long-lived process, checking for updates every
+    /// `poll_interval_seconds`.  SIGTERM or SIGINT make us stop once the
+    /// current task is done, and SIGHUP reloads the config file and repo
+    /// configs.
+    pub fn daemon(&mut self, config_path: &str) -> Result<(), Error> {
+        let reload = Arc::new(AtomicBool::new(false));
+        self.register_stop_signals()?;
+        signal_hook::flag::register(signal_hook::SIGHUP, reload.clone())
+            .context("could not register SIGHUP handler")?;
+
+        self.lock_and_load_state()?;
+        self.load_repo_configs()?;
+
+        if let Some(address) = &self.config.metrics_address {
+            metrics::serve(address)?;
+        }
+
+        let poll_interval = Duration::from_secs(self.config.poll_interval_seconds);
+        loop {
+            if reload.swap(false, Ordering::SeqCst) {
+                if let Err(e) = self.reload_config(config_path) {
+                    print_error(&e);
+                }
+            }
+
+            self.poll();
+
+            let next_poll = Instant::now() + poll_interval;
+            while Instant::now() < next_poll && !reload.load(Ordering::SeqCst) {
+                if !self.sleep(Duration::from_secs(1)) {
+                    break;
+                }
+            }
+            if self.stop.load(Ordering::SeqCst) {
+                print_info("stopping");
+                return Ok(());
+            }
+        }
+    }
+
+    /// Makes SIGTERM and SIGINT ask us to stop.
+    fn register_stop_signals(&self) -> Result<(), Error> {
+        signal_hook::flag::register(signal_hook::SIGTERM, self.stop.clone())
+            .context("could not register SIGTERM handler")?;
+        signal_hook::flag::register(signal_hook::SIGINT, self.stop.clone())
+            .context("could not register SIGINT handler")?;
+        Ok(())
+    }
+
+    /// Checks for updates and processes the queue, for a long-running
+    /// process.  Errors, such as failing to save the state or GitHub
+    /// rejecting our credentials, are logged rather than returned, so that
+    /// we try again at the next poll.
+    fn poll(&mut self) {
+        if let Err(e) = self.check_for_updates().and_then(|_| self.process_queue()) {
+            print_error(&e);
+        }
+    }
+
+    /// Returns the flag that asks us to stop, as SIGTERM does.
+    #[cfg(test)]
+    pub fn stop_handle(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    /// Snapshots the state if it is time to, and prunes old parts of it,
+    /// then queues tasks to look for updates.
+    fn check_for_updates(&mut self) -> Result<(), Error> {
+        let interval = Duration::from_secs(self.config.state_snapshot_interval_seconds);
+        if !matches!(self.last_snapshot, Some(t) if t.elapsed() < interval) {
+            self.snapshot_state()?;
+            self.last_snapshot = Some(Instant::now());
+        }
+        let stats = self.state.gc(&self.config);
+        if stats.comments_pruned > 0 || stats.issues_pruned > 0 {
+            print_info(&format!(
+                "pruned {} handled comments and {} handled decisions issues",
+                stats.comments_pruned, stats.issues_pruned
+            ));
+        }
+        self.state.check_for_updates(&self.config);
+        Ok(())
+    }
+
+    /// Re-reads the config file and the decisions repositories' repo
+    /// configs.  The old ones are kept if either can't be loaded, or if the
+    /// new config moves the state, which needs a restart.
+    fn reload_config(&mut self, config_path: &str) -> Result<(), Error> {
+        print_info("reloading config");
+        let mut config = Config::from_file(config_path)?;
+        if config.state_directory != self.config.state_directory
+            || config.state_backend != self.config.state_backend
+        {
+            return Err(format_err!(
+                "state_directory and state_backend can't be changed without restarting"
+            ));
+        }
+        config.dry_run = self.config.dry_run;
+        let github = Box::new(GitHub::new(&config));
+        let bug_tracker = Box::new(Bugzilla::new(&config));
+        let old_config = mem::replace(&mut self.config, config);
+        let old_github = mem::replace(&mut self.github, github);
+        let old_bug_tracker = mem::replace(&mut self.bug_tracker, bug_tracker);
+        let old_repo_configs = mem::take(&mut self.repo_configs);
+        if let Err(e) = self.load_repo_configs() {
+            self.config = old_config;
+            self.github = old_github;
+            self.bug_tracker = old_bug_tracker;
+            self.repo_configs = old_repo_configs;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Sleeps for the given duration, returning early if we have been asked
+    /// to stop.  Returns false if we have.
+    fn sleep(&self, duration: Duration) -> bool {
+        let end = Instant::now() + duration;
+        loop {
+            if self.stop.load(Ordering::SeqCst) {
+                return false;
+            }
+            let now = Instant::now();
+            if now >= end {
+                return true;
+            }
+            thread::sleep(cmp::min(end - now, Duration::from_secs(1)));
+        }
+    }
+
+    /// Fetches the repo config file from each decisions repository.
+    fn load_repo_configs(&mut self) -> Result<(), Error> {
+        for decisions_repo in self.config.decisions_repos() {
+            let (owner, name) = split_repo_id(&decisions_repo);
+            let repo_config_toml = self.github.repo_config(owner, name)?;
+
+            let repo_config = RepoConfig::from_str(&repo_config_toml)
+                .with_context(|_| format!("could not load repo config for {}", decisions_repo))?;
+            self.repo_configs.insert(decisions_repo, repo_config);
+        }
+        Ok(())
+    }
+
+    /// Runs tasks until the queue is empty, or until only tasks waiting to
+    /// be retried later remain.
+    fn process_queue(&mut self) -> Result<(), Error> {
+        loop {
+            if !self.wait_for_rate_limit() || self.stop.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+            let result = self.state.iterate(
+                &self.config,
+                &self.repo_configs,
+                &mut *self.github,
+                &mut *self.bug_tracker,
+            );
+            self.state.record_metrics();
+            self.save_state()?;
+            if let Err(e) = result {
+                if matches!(e.downcast_ref::<QueryError>(), Some(e) if e.is_fatal()) {
+                    return Err(e);
+                }
+                // The failed task has been logged, and rescheduled or moved
+                // to the dead task list, so carry on with the rest of the
+                // queue.
+            }
             if self.state.is_finished() {
                 return Ok(());
             }
         }
     }
 
+    /// Checks whether the GitHub API rate limit is running low, and if so,
+    /// sleeps until it is reset.  Returns false if the reset is too far away
+    /// to wait for, or we were asked to stop while waiting, in which case the
+    /// run should stop and leave the remaining tasks for next time.
+    fn wait_for_rate_limit(&self) -> bool {
+        let rate_limit = match query::rate_limit() {
+            Some(rate_limit) if rate_limit.remaining < self.config.rate_limit_minimum => rate_limit,
+            _ => return true,
+        };
+
+        let wait = rate_limit.reset_at - Utc::now().timestamp();
+        if wait < 0 {
+            // The rate limit has already been reset since the last request.
+            return true;
+        }
+        if wait > self.config.rate_limit_max_sleep_seconds {
+            print_info(&format!(
+                "stopping: GitHub rate limit has {} points remaining, resetting in {}s",
+                rate_limit.remaining, wait
+            ));
+            return false;
+        }
+
+        print_info(&format!(
+            "sleeping: GitHub rate limit has {} points remaining, resetting in {}s",
+            rate_limit.remaining, wait
+        ));
+        self.sleep(Duration::from_secs(wait as u64 + 1))
+    }
+
+    pub fn status(&mut self) -> Result<(), Error> {
+        self.load_state_read_only()?;
+        self.state.print_status();
+        Ok(())
+    }
+
+    /// Prunes old handled comments and decisions issues, and reports how
+    /// much was reclaimed.
+    pub fn gc(&mut self) -> Result<(), Error> {
+        self.lock_and_load_state()?;
+        let stats = self.state.gc(&self.config);
+        println!(
+            "handled comments: {} pruned, {} kept",
+            stats.comments_pruned, stats.comments_kept
+        );
+        println!(
+            "handled decisions issues: {} pruned, {} kept",
+            stats.issues_pruned, stats.issues_kept
+        );
+        if !self.saves_state() {
+            return Ok(());
+        }
+
+        self.snapshot_state()?;
+        let size_before = self.store.size()?;
+        self.save_state()?;
+        self.store.compact()?;
+        let size_after = self.store.size()?;
+        println!(
+            "state size: {} bytes, was {} bytes",
+            size_after, size_before
+        );
+        Ok(())
+    }
+
+    pub fn list_queue(&mut self) -> Result<(), Error> {
+        self.load_state_read_only()?;
+        self.state.print_queue();
+        Ok(())
+    }
+
+    pub fn drop_task(&mut self, index: usize, dead: bool) -> Result<(), Error> {
+        self.lock_and_load_state()?;
+        let task = self.state.drop_task(index, dead)?;
+        println!("dropped {}", task);
+        self.save_state()
+    }
+
+    pub fn retry_tasks(&mut self) -> Result<(), Error> {
+        self.lock_and_load_state()?;
+        self.state.retry_tasks();
+        self.save_state()
+    }
+
+    pub fn reprocess(
+        &mut self,
+        wg_repo: Option<&str>,
+        kind: IssueKind,
+        wg_issue_number: i64,
+    ) -> Result<(), Error> {
+        let wg_repo = match wg_repo {
+            Some(id) => self.config.wg_repo(id)?.id(),
+            None => self.config.wg_repos[0].id(),
+        };
+        self.lock_and_load_state()?;
+        self.state
+            .reprocess_wg_issue(&wg_repo, kind, wg_issue_number);
+        self.save_state()
+    }
+
+    /// Loads the state, upgrading it to the current version, and saves it
+    /// again without processing any updates.
+    pub fn migrate(&mut self) -> Result<(), Error> {
+        self.lock_and_load_state()?;
+        if self.saves_state() {
+            self.snapshot_state()?;
+            self.save_state()?;
+        }
+        print_info(&format!("state is at version {}", STATE_VERSION));
+        Ok(())
+    }
+
+    /// Replaces the state with a snapshot, after checking that the snapshot
+    /// can be loaded.  The current state is snapshotted first, so that this
+    /// can be undone.
+    pub fn restore(&mut self, snapshot: &str) -> Result<(), Error> {
+        let path = self.store.snapshot_path(snapshot)?;
+        let mut state = VersionedState::from_path(&path, &self.config)
+            .context(format!("could not load snapshot {}", path.display()))?;
+        self.lock_and_load_state()?;
+        if !self.saves_state() {
+            println!("[dry run] RestoreState {}", path.display());
+            state.print_status();
+            return Ok(());
+        }
+        self.snapshot_state()?;
+        self.store.replace(&mut state)?;
+        self.state = state;
+        print_info(&format!("restored state from {}", path.display()));
+        Ok(())
+    }
+
+    /// Loads the state, or when replaying a recording, the state the
+    /// recorded run started from.  When recording, the loaded state is saved
+    /// in the recording.
+    fn load_state(&mut self, read_only: bool) -> Result<(), Error> {
+        if let Some(path) = query::replayed_state_path() {
+            self.state = VersionedState::from_path(&path, &self.config)
+                .context("could not load recorded state")?;
+            return Ok(());
+        }
+        self.state = self.store.load(&self.config, read_only)?;
+        if let Some(path) = query::recorded_state_path() {
+            self.state
+                .save(&path, &path.with_extension("temp"))
+                .context("could not record state")?;
+        }
+        Ok(())
+    }
+
+    /// Returns whether the state, and anything else in the state directory,
+    /// may be written.  They aren't in dry-run mode, or when replaying a
+    /// recording, since the state came from the recording.
+    fn saves_state(&self) -> bool {
+        !self.config.dry_run && query::replayed_state_path().is_none()
+    }
+
+    fn snapshot_state(&self) -> Result<(), Error> {
+        if !self.saves_state() {
+            return Ok(());
+        }
+        self.store
+            .snapshot(&self.state, self.config.state_snapshots)
+    }
+
+    /// Locks and loads the state for a command that may save it.  It is
+    /// loaded read-only if it won't be saved.
+    fn lock_and_load_state(&mut self) -> Result<(), Error> {
+        if !self.try_lock()? {
+            return Err(format_err!("another wg-tracker instance is running"));
+        }
+        self.load_state(!self.saves_state())
+    }
+
+    /// Loads the state for a command that never saves it.  This doesn't take
+    /// the lock, so that the state can be looked at while another instance
+    /// is running: the JSON state file is replaced atomically, and the state
+    /// database is only written in transactions.
+    fn load_state_read_only(&mut self) -> Result<(), Error> {
+        self.load_state(true)
+    }
+
+    fn save_state(&mut self) -> Result<(), Error> {
+        if !self.saves_state() {
+            return Ok(());
+        }
+        self.store.save(&mut self.state)
+    }
+
     /// Attempts to lock the lockfile, to prevent simultanteous wg-tracker
     /// instances from running.
     fn try_lock(&mut self) -> Result<bool, Error> {
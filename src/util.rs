@@ -1,6 +1,27 @@
+use crate::logging;
+use failure::Error;
 use lazy_static::lazy_static;
 use regex::{Captures, Regex};
 
+pub fn print_info(message: &str) {
+    logging::info(message, &[]);
+}
+
+pub fn print_error(e: &Error) {
+    logging::error(&error_message(e), &[]);
+}
+
+/// Returns the error message along with that of its immediate cause.
+pub fn error_message(e: &Error) -> String {
+    format!(
+        "{}{}",
+        e,
+        e.as_fail()
+            .cause()
+            .map_or(String::new(), |f| format!(": {}", f))
+    )
+}
+
 pub fn escape_markdown(s: &str) -> String {
     ESCAPE_MARKDOWN_RE
         .replace_all(s, |cs: &Captures| {
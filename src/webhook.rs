@@ -0,0 +1,393 @@
+use crate::query::{IssueKind, IssueLabel};
+use crate::util::{error_message, print_info};
+use failure::{format_err, Error, ResultExt};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::io::Read;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use tiny_http::{Request, Response, Server};
+
+/// The largest request body we accept, which is the most GitHub will send.
+const MAX_BODY_SIZE: u64 = 25 * 1024 * 1024;
+
+/// A GitHub webhook delivery that we might need to act on.
+#[derive(Debug)]
+pub enum WebhookEvent {
+    /// A comment was created or edited.
+    IssueComment {
+        repo: String,
+        /// Whether the comment is on an issue, pull request or discussion.
+        kind: IssueKind,
+        issue_number: i64,
+        issue_title: String,
+        issue_labels: Vec<IssueLabel>,
+        /// When the comment was created.  We query for the comment rather
+        /// than using the payload's copy, since that has the markdown body
+        /// rather than the text that resolutions are looked for in.
+        created_at: String,
+    },
+    /// An issue was opened, edited, labeled, etc.
+    Issue {
+        repo: String,
+        issue_number: i64,
+        issue_id: String,
+        issue_labels: Vec<IssueLabel>,
+    },
+}
+
+#[derive(Deserialize)]
+struct IssueCommentPayload {
+    action: String,
+    repository: RepositoryPayload,
+    issue: IssuePayload,
+    comment: CommentPayload,
+}
+
+#[derive(Deserialize)]
+struct DiscussionCommentPayload {
+    action: String,
+    repository: RepositoryPayload,
+    discussion: IssuePayload,
+    comment: CommentPayload,
+}
+
+#[derive(Deserialize)]
+struct IssuesPayload {
+    repository: RepositoryPayload,
+    issue: IssuePayload,
+}
+
+#[derive(Deserialize)]
+struct RepositoryPayload {
+    full_name: String,
+}
+
+#[derive(Deserialize)]
+struct IssuePayload {
+    node_id: String,
+    number: i64,
+    title: String,
+    /// Not every discussion payload includes labels.
+    #[serde(default)]
+    labels: Vec<IssueLabel>,
+    /// Present if the issue is a pull request.
+    #[serde(default)]
+    pull_request: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct CommentPayload {
+    created_at: String,
+}
+
+/// Starts a thread that listens for GitHub webhook deliveries on the given
+/// address, and returns a channel that the verified events are sent to.
+pub fn listen(address: &str, secret: &str) -> Result<Receiver<WebhookEvent>, Error> {
+    let server = Server::http(address)
+        .map_err(|e| format_err!("could not start webhook server on {}: {}", address, e))?;
+    let secret = secret.to_string();
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            if !handle_request(request, &secret, &sender) {
+                // The receiver has gone away, so we're shutting down.
+                return;
+            }
+        }
+    });
+
+    Ok(receiver)
+}
+
+/// Responds to a single webhook delivery.  Returns false if the event could
+/// not be passed on.
+fn handle_request(mut request: Request, secret: &str, sender: &Sender<WebhookEvent>) -> bool {
+    let header = |name: &'static str| {
+        request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv(name))
+            .map(|h| h.value.as_str().to_string())
+    };
+    let event_type = header("X-GitHub-Event").unwrap_or_default();
+    let signature = header("X-Hub-Signature-256").unwrap_or_default();
+
+    let (status, message, event) =
+        check_delivery(secret, &event_type, &signature, request.as_reader());
+    if let Some(event) = event {
+        print_info(&format!("received {} webhook", event_type));
+        if sender.send(event).is_err() {
+            return false;
+        }
+    }
+
+    // Nothing useful can be done if GitHub has gone away before we respond.
+    let _ = request.respond(Response::from_string(message).with_status_code(status));
+    true
+}
+
+/// Reads, verifies and parses a delivery, returning the status and message
+/// to respond with, and the event if there is one to act on.
+fn check_delivery(
+    secret: &str,
+    event_type: &str,
+    signature: &str,
+    body: impl Read,
+) -> (u16, String, Option<WebhookEvent>) {
+    let mut bytes = Vec::new();
+    if body
+        .take(MAX_BODY_SIZE + 1)
+        .read_to_end(&mut bytes)
+        .is_err()
+    {
+        return (400, String::from("could not read request body"), None);
+    }
+    if bytes.len() as u64 > MAX_BODY_SIZE {
+        return (413, String::from("request body too large"), None);
+    }
+    if !verify_signature(secret, &bytes, signature) {
+        return (401, String::from("bad signature"), None);
+    }
+    match parse_event(event_type, &bytes) {
+        Ok(Some(event)) => (202, String::from("accepted"), Some(event)),
+        Ok(None) => (200, String::from("ignored"), None),
+        Err(e) => (400, error_message(&e), None),
+    }
+}
+
+/// Checks an `X-Hub-Signature-256` header value against the HMAC of the
+/// request body.
+fn verify_signature(secret: &str, body: &[u8], signature: &str) -> bool {
+    let signature = match signature
+        .strip_prefix("sha256=")
+        .and_then(|s| hex::decode(s).ok())
+    {
+        Some(signature) => signature,
+        None => return false,
+    };
+    let mut mac = Hmac::<Sha256>::new_varkey(secret.as_bytes()).unwrap();
+    mac.input(body);
+    mac.verify(&signature).is_ok()
+}
+
+fn parse_event(event_type: &str, body: &[u8]) -> Result<Option<WebhookEvent>, Error> {
+    let event = match event_type {
+        "issue_comment" => {
+            let payload: IssueCommentPayload =
+                serde_json::from_slice(body).context("could not parse issue_comment payload")?;
+            if payload.action == "deleted" {
+                return Ok(None);
+            }
+            WebhookEvent::IssueComment {
+                repo: payload.repository.full_name,
+                kind: if payload.issue.pull_request.is_some() {
+                    IssueKind::PullRequest
+                } else {
+                    IssueKind::Issue
+                },
+                issue_number: payload.issue.number,
+                issue_title: payload.issue.title,
+                issue_labels: payload.issue.labels,
+                created_at: payload.comment.created_at,
+            }
+        }
+        "discussion_comment" => {
+            let payload: DiscussionCommentPayload = serde_json::from_slice(body)
+                .context("could not parse discussion_comment payload")?;
+            if payload.action == "deleted" {
+                return Ok(None);
+            }
+            WebhookEvent::IssueComment {
+                repo: payload.repository.full_name,
+                kind: IssueKind::Discussion,
+                issue_number: payload.discussion.number,
+                issue_title: payload.discussion.title,
+                issue_labels: payload.discussion.labels,
+                created_at: payload.comment.created_at,
+            }
+        }
+        "issues" => {
+            let payload: IssuesPayload =
+                serde_json::from_slice(body).context("could not parse issues payload")?;
+            WebhookEvent::Issue {
+                repo: payload.repository.full_name,
+                issue_number: payload.issue.number,
+                issue_id: payload.issue.node_id,
+                issue_labels: payload.issue.labels,
+            }
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(event))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::io;
+
+    const SECRET: &str = "It's a Secret to Everybody";
+
+    fn sign(body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_varkey(SECRET.as_bytes()).unwrap();
+        mac.input(body);
+        format!("sha256={}", hex::encode(mac.result().code()))
+    }
+
+    fn issue_comment(action: &str, pull_request: bool) -> Vec<u8> {
+        let mut payload = json!({
+            "action": action,
+            "repository": { "full_name": "w3c/csswg-drafts" },
+            "issue": {
+                "node_id": "MDU6SXNzdWUxMjM0",
+                "number": 1234,
+                "title": "[css-grid] Clarify min-content contribution",
+                "labels": [{ "name": "css-grid-2", "color": "fbca04" }],
+            },
+            "comment": { "created_at": "2019-03-01T17:00:00Z" },
+        });
+        if pull_request {
+            payload["issue"]["pull_request"] = json!({});
+        }
+        payload.to_string().into_bytes()
+    }
+
+    #[test]
+    fn signatures_are_checked() {
+        let body = issue_comment("created", false);
+        let signature = sign(&body);
+        assert!(verify_signature(SECRET, &body, &signature));
+        assert!(!verify_signature(SECRET, &body, ""));
+        assert!(!verify_signature("another secret", &body, &signature));
+        assert!(!verify_signature(SECRET, b"{}", &signature));
+        // The signature must be hex, with the sha256= prefix.
+        assert!(!verify_signature(
+            SECRET,
+            &body,
+            &signature["sha256=".len()..]
+        ));
+        assert!(!verify_signature(
+            SECRET,
+            &body,
+            &signature.replace("sha256=", "sha1=")
+        ));
+        assert!(!verify_signature(SECRET, &body, "sha256=not hex"));
+    }
+
+    #[test]
+    fn deliveries_without_valid_signatures_are_rejected() {
+        let body = issue_comment("created", false);
+        let (status, _, event) = check_delivery(SECRET, "issue_comment", "", &body[..]);
+        assert_eq!(status, 401);
+        assert!(event.is_none());
+
+        let signature = sign(b"something else");
+        let (status, _, event) = check_delivery(SECRET, "issue_comment", &signature, &body[..]);
+        assert_eq!(status, 401);
+        assert!(event.is_none());
+
+        let (status, _, event) = check_delivery(SECRET, "issue_comment", &sign(&body), &body[..]);
+        assert_eq!(status, 202);
+        assert!(event.is_some());
+    }
+
+    #[test]
+    fn large_deliveries_are_rejected() {
+        let body = io::repeat(b' ').take(MAX_BODY_SIZE + 1);
+        let (status, _, event) = check_delivery(SECRET, "issue_comment", "", body);
+        assert_eq!(status, 413);
+        assert!(event.is_none());
+    }
+
+    #[test]
+    fn comment_events_are_parsed() {
+        match parse_event("issue_comment", &issue_comment("edited", false)) {
+            Ok(Some(WebhookEvent::IssueComment {
+                repo,
+                kind,
+                issue_number,
+                issue_title,
+                issue_labels,
+                created_at,
+            })) => {
+                assert_eq!(repo, "w3c/csswg-drafts");
+                assert_eq!(kind, IssueKind::Issue);
+                assert_eq!(issue_number, 1234);
+                assert_eq!(issue_title, "[css-grid] Clarify min-content contribution");
+                assert_eq!(issue_labels[0].name, "css-grid-2");
+                assert_eq!(created_at, "2019-03-01T17:00:00Z");
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+
+        match parse_event("issue_comment", &issue_comment("created", true)) {
+            Ok(Some(WebhookEvent::IssueComment { kind, .. })) => {
+                assert_eq!(kind, IssueKind::PullRequest)
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+
+        let discussion_comment = json!({
+            "action": "created",
+            "repository": { "full_name": "w3c/csswg-drafts" },
+            "discussion": {
+                "node_id": "D_kwDOAbcd",
+                "number": 5000,
+                "title": "Discuss",
+            },
+            "comment": { "created_at": "2019-03-01T17:00:00Z" },
+        });
+        match parse_event(
+            "discussion_comment",
+            discussion_comment.to_string().as_bytes(),
+        ) {
+            Ok(Some(WebhookEvent::IssueComment {
+                kind,
+                issue_number,
+                issue_labels,
+                ..
+            })) => {
+                assert_eq!(kind, IssueKind::Discussion);
+                assert_eq!(issue_number, 5000);
+                assert!(issue_labels.is_empty());
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+
+    #[test]
+    fn other_events_are_parsed_or_ignored() {
+        let issues = json!({
+            "action": "labeled",
+            "repository": { "full_name": "mozilla/wg-decisions" },
+            "issue": {
+                "node_id": "MDU6SXNzdWU0Mg==",
+                "number": 42,
+                "title": "[css-grid] Clarify min-content contribution",
+                "labels": [{ "name": "bug", "color": "d73a4a" }],
+            },
+        });
+        match parse_event("issues", issues.to_string().as_bytes()) {
+            Ok(Some(WebhookEvent::Issue {
+                repo,
+                issue_number,
+                issue_id,
+                issue_labels,
+            })) => {
+                assert_eq!(repo, "mozilla/wg-decisions");
+                assert_eq!(issue_number, 42);
+                assert_eq!(issue_id, "MDU6SXNzdWU0Mg==");
+                assert_eq!(issue_labels[0].name, "bug");
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+
+        let deleted = issue_comment("deleted", false);
+        assert!(parse_event("issue_comment", &deleted).unwrap().is_none());
+        assert!(parse_event("ping", b"{}").unwrap().is_none());
+        assert!(parse_event("issue_comment", b"{}").is_err());
+    }
+}